@@ -0,0 +1,37 @@
+extern crate test;
+extern crate section_str;
+extern crate section_bytes;
+
+use std::io::MemWriter;
+use test::BenchHarness;
+
+/// Number of sections making up the large page each iteration renders
+static SECTIONS: uint = 100;
+
+fn items() -> ~[~str] {
+  range(0, 10).map(|n| format!("item <{}>", n)).collect()
+}
+
+#[bench]
+fn bench_write_str(b: &mut BenchHarness) {
+  let items = items();
+
+  b.iter(|| {
+    let mut writer = MemWriter::new();
+    for n in range(0, SECTIONS) {
+      section_str::Section(&mut writer, n, items.as_slice()).unwrap();
+    }
+  });
+}
+
+#[bench]
+fn bench_write_bytes(b: &mut BenchHarness) {
+  let items = items();
+
+  b.iter(|| {
+    let mut writer = MemWriter::new();
+    for n in range(0, SECTIONS) {
+      section_bytes::Section(&mut writer, n, items.as_slice()).unwrap();
+    }
+  });
+}
@@ -1,26 +1,160 @@
 extern crate ers;
 
 use std::os;
-use std::io::BufferedWriter;
+use std::io::stdio::{stderr, stdin, stdout};
+use std::io::{BufferedWriter, MemWriter};
 use std::io::fs::File;
-use ers::{Parser};
+use ers::{Parser, WriteOptions, Interpreter, Context};
+use ers::sourcemap::SourceMap;
+use ers::template::{DeclarationNotFound, WriteFailure};
+
+/**
+  `explain_errors` copies the compiler output read from the standard input,
+  pointing the spans in generated code back to the templates described by
+  the source map at `path`
+  */
+fn explain_errors(path: &str) {
+  let map = match File::open(&Path::new(path)).and_then(|mut file| file.read_to_str()) {
+    Ok(source) => {
+      match SourceMap::parse(source) {
+        Ok(map)      => map,
+        Err(message) => {
+          let _ = stderr().write_line(format!("ers: {}: {}", path, message));
+          os::set_exit_status(1);
+          return;
+        }
+      }
+    },
+    Err(error) => {
+      let _ = stderr().write_line(format!("ers: {}: {}", path, error));
+      os::set_exit_status(1);
+      return;
+    }
+  };
+
+  for line in stdin().lines() {
+    match line {
+      Ok(line) => println!("{}", map.explain(line.trim_right())),
+      Err(_)   => break
+    }
+  }
+}
+
+/**
+  `render` interprets the template at `path` against the JSON object in the
+  file at `data`, writing the function called `name`, or the first one, to
+  the standard output
+  */
+fn render(path: &str, data: &str, name: Option<&str>) {
+  let mut context = match File::open(&Path::new(data)).and_then(|mut file| file.read_to_str()) {
+    Ok(source) => {
+      match Context::from_json(source.as_slice()) {
+        Ok(context)  => context,
+        Err(message) => {
+          let _ = stderr().write_line(format!("ers: {}: {}", data, message));
+          os::set_exit_status(1);
+          return;
+        }
+      }
+    },
+    Err(error) => {
+      let _ = stderr().write_line(format!("ers: {}: {}", data, error));
+      os::set_exit_status(1);
+      return;
+    }
+  };
+
+  let template = match Parser::new().parse_path(path.to_owned()) {
+    Ok(template) => template,
+    Err(error)   => {
+      let _ = stderr().write_line(format!("ers: {}", error));
+      os::set_exit_status(1);
+      return;
+    }
+  };
+
+  let interpreter = Interpreter::new(&template);
+  let mut writer  = BufferedWriter::new(stdout());
+  let result      = match name {
+    Some(name) => interpreter.render_function(name, &mut context, &mut writer),
+    None       => interpreter.render(&mut context, &mut writer)
+  };
+  let _ = writer.flush();
+
+  match result {
+    Ok(())     => {},
+    Err(error) => {
+      let _ = stderr().write_line(format!("ers: {}", error));
+      os::set_exit_status(1);
+    }
+  }
+}
 
 fn main() {
-  let args = os::args();
+  let mut options = WriteOptions::new();
+  let mut args    = ~[];
+
+  for arg in os::args().move_iter().skip(1) {
+    match arg.as_slice() {
+      "--to-string"      => { options.to_string = true; },
+      "--generic-writer" => { options.generic_writer = true; },
+      "--byte-strings"   => { options.byte_strings = true; },
+      _                  => args.push(arg)
+    }
+  }
+
+  if args.len() == 2 && args[0].as_slice() == "explain-errors" {
+    explain_errors(args[1].as_slice());
+    return;
+  }
+
+  if (args.len() == 3 || args.len() == 4) && args[0].as_slice() == "render" {
+    render(args[1].as_slice(), args[2].as_slice(), args.get(3).map(|name| name.as_slice()));
+    return;
+  }
 
   match args.len() {
-    3 => {
-      let template = Parser::new().parse_path(args[1].clone());
+    2 => {
+      let template = Parser::new().parse_path(args[0].clone());
 
       match template {
-        Some(template) => {
-          let mut out_writer = BufferedWriter::new(~File::create(&Path::new(args[2].clone())).unwrap() as ~Writer);
+        Ok(template) => {
+          // Generated first, so a template that fails to validate leaves no file behind
+          let mut generated = MemWriter::new();
+          let mut map       = SourceMap::new(args[1].clone());
 
-          template.write_formatted(&mut out_writer);
+          match template.write_formatted_mapped(&mut generated, &options, &mut map) {
+            Ok(_) => {
+              let result = File::create(&Path::new(args[1].clone())).
+                and_then(|mut file| file.write(generated.get_ref())).
+                and_then(|_| {
+                  File::create(&Path::new(format!("{}.map", args[1]))).
+                    and_then(|mut file| map.write(&mut file))
+                });
 
-          out_writer.flush();
+              match result {
+                Ok(())     => {},
+                Err(error) => {
+                  let _ = stderr().write_line(format!("ers: {}: {}", args[1], error));
+                  os::set_exit_status(1);
+                }
+              }
+            },
+            Err(error) => {
+              // Errors with a position start with their `path:line:column`
+              let message = match error {
+                DeclarationNotFound | WriteFailure(_) => format!("{}: {}", template.path, error),
+                _                                     => format!("{}", error)
+              };
+              let _ = stderr().write_line(format!("ers: {}", message));
+              os::set_exit_status(1);
+            }
+          }
         },
-        _ => fail!("Something went wrong !")
+        Err(error) => {
+          let _ = stderr().write_line(format!("ers: {}", error));
+          os::set_exit_status(1);
+        }
       }
     },
     _ => {
@@ -30,7 +164,19 @@ This program comes with ABSOLUTELY NO WARRANTY; for details type `open LICENSE.t
 This is free software, and you are welcome to redistribute it
 under certain conditions; type `make license' for details.
 
- λ ers <input-file> <output-file>
+ λ ers [--to-string] [--generic-writer] [--byte-strings] <input-file> <output-file>
+ λ rustc ... 2>&1 | ers explain-errors <output-file>.map
+ λ ers render <input-file> <data.json> [<function>]
+
+   --to-string       also generate a `<name>_to_string` function for every
+                     template function
+   --generic-writer  make template functions generic over their writer,
+                     declared without a `writer` argument
+   --byte-strings    write static text as byte strings
+
+   render            write the template rendered against the JSON object in
+                     <data.json> without compiling it, using the function
+                     called <function> or the first one
 
  e.g: bin/ers my-file.ers my-file-template.rs");
     }
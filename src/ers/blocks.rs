@@ -14,6 +14,12 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::io::{IoResult, IoError, InvalidInput};
+use signature::Signature;
+
+/// Return type given to declarations that do not name one
+pub static RETURN_TYPE: &'static str = "::std::io::IoResult<()>";
+
 /**
 `Class` is the base type of a `Block`.
 */
@@ -31,8 +37,70 @@ pub enum Class {
   /// Represents simple portions of text
   Text,
 
-  /// Will output the result of the execution of the Rust code it holds
-  Print
+  /// Will output the HTML-escaped result of the execution of the Rust code
+  /// it holds, formatted with `{}`
+  Print,
+
+  /// Will output the result of the execution of the Rust code it holds,
+  /// formatted with `{}` and without escaping it
+  Raw,
+
+  /// Will output the HTML-escaped result of the execution of the Rust code
+  /// it holds, formatted with `{:?}`
+  Debug,
+
+  /// Holds a note left in the template, never written to the output
+  Comment,
+
+  /// Holds an instruction for the `Parser`, like `include "path"`
+  Directive,
+
+  /// Holds a call to another template function, like `user_card(&user)`,
+  /// which is given the current writer
+  Partial,
+
+  /// Marks where a layout inserts the slot named by its content. Slots that
+  /// are not filled write nothing
+  Yield,
+
+  /// Opens an `<% if condition %>` tag, its content being the condition
+  If,
+
+  /// Starts an `<% elsif condition %>` branch of the enclosing `If`
+  Elsif,
+
+  /// Starts the `<% else %>` branch of the enclosing `If`
+  Else,
+
+  /// Opens a `<% for x in xs %>` loop, its content being `x in xs`
+  For,
+
+  /// Closes the enclosing `If` or `For` with `<% end %>`
+  End
+}
+
+/**
+Location
+*/
+#[deriving(Eq, Clone)]
+pub struct Location {
+  /// Line number, starting at 1
+  line_no: int,
+
+  /// Column number in characters, starting at 1
+  column: int,
+
+  /// Byte offset from the beginning of the template
+  offset: uint
+}
+
+impl Location {
+  /**
+    Creates a new `Location`
+    */
+  pub fn new(line_no: int, column: int, offset: uint) -> Location {
+    Location{line_no: line_no, column: column, offset: offset}
+  }
 }
 
 /**
@@ -40,17 +108,49 @@ Pos
 */
 #[deriving(Eq, Clone)]
 pub struct Pos {
-  /// Internal line number
-  line_no: int
+  /// Location of the first character of the block, e.g. the `<` of `<%`
+  start: Location,
+
+  /// Location right after the last character of the block
+  end: Location
 }
 
 impl Pos {
+  /**
+    Creates a new `Pos` spanning from `start` to `end`
+    */
+  pub fn new(start: Location, end: Location) -> Pos {
+    Pos{start: start, end: end}
+  }
+
+  /**
+    `merge` returns the `Pos` spanning from the start of `self` to the end
+    of `other`
+    */
+  pub fn merge(&self, other: &Pos) -> Pos {
+    Pos{start: self.start, end: other.end}
+  }
+
   /**
     `write` will write the `Pos` content to the `writer`
     */
-  #[allow(unused_must_use)]
-  pub fn write(&self, writer:&mut Writer) {
-    writer.write_line(format!("\n//line {:d}", self.line_no));
+  pub fn write(&self, writer:&mut Writer) -> IoResult<()> {
+    writer.write_line(format!("\n//line {:d}", self.start.line_no))
+  }
+}
+
+/**
+  `split_call` splits the content of a `Partial` block, like
+  `user_card(&user)`, into the name of the function and its arguments
+  */
+pub fn split_call<'a>(call: &'a str) -> Option<(&'a str, &'a str)> {
+  let call = call.trim();
+
+  match call.find('(') {
+    Some(index) if index > 0 && call.ends_with(")") => {
+      Some((call.slice_to(index).trim(), call.slice(index + 1, call.len() - 1).trim()))
+    },
+    _ => None
   }
 }
 
@@ -66,40 +166,91 @@ pub struct Block {
   content: ~str,
 
   /// Block's position in the template
-  pos: Pos
+  pos: Pos,
+
+  /// Location of the template the block was read from
+  path: ~str
 }
 
 impl Block {
   /**
-    `write` will write the block's content to the `writer`
+    `write` will write the block's content to the `writer`. Generated writes
+    return their errors to the caller of the template function
     */
-  #[allow(unused_must_use)]
-  pub fn write(&self, writer:&mut Writer) {
+  pub fn write(&self, writer:&mut Writer) -> IoResult<()> {
+    if self.class == Comment || self.class == Directive || self.class == Yield {
+      return Ok(());
+    }
+
     let mut w = writer;
-    self.pos.write(&mut w);
+    try!(self.pos.write(&mut w));
 
     let content = self.content.escape_default();
     match self.class {
       Header      => {
-        w.write_line(content);
+        w.write_line(content)
       },
       Declaration => {
-        w.write_str(content);
-        w.write_str(" {\n");
+        let signature = match Signature::parse(self.content.as_slice()) {
+          Some(signature) => signature,
+          None            => {
+            return Err(IoError{kind: InvalidInput, desc: "unsupported declaration", detail: Some(self.content.clone())});
+          }
+        };
+        match signature.output {
+          Some(_) => try!(w.write_str(content)),
+          None    => try!(w.write_str(format!("{} -> {}", content.trim_right(), RETURN_TYPE)))
+        }
+        w.write_str(" {\n")
       },
       Text => {
         w.write_str(format!(
-            "writer.write_str(\"{:s}\");\n",
+            "try!(writer.write_str(\"{:s}\"));\n",
             content
-            ));
+            ))
       },
       Print => {
-        w.write_str("writer.write_str(format!(\"{:?}\", ");
-        w.write_str(format!("{:s}", content));
-        w.write_str("));\n");
+        try!(w.write_str("try!(writer.write_str(ers::runtime::escape_html(format!(\"{}\", "));
+        try!(w.write_str(self.content.as_slice()));
+        w.write_str("))));\n")
+      },
+      Raw => {
+        try!(w.write_str("try!(writer.write_str(format!(\"{}\", "));
+        try!(w.write_str(self.content.as_slice()));
+        w.write_str(")));\n")
+      },
+      Debug => {
+        try!(w.write_str("try!(writer.write_str(ers::runtime::escape_html(format!(\"{:?}\", "));
+        try!(w.write_str(self.content.as_slice()));
+        w.write_str("))));\n")
+      },
+      If => {
+        try!(w.write_str("if "));
+        try!(w.write_str(self.content.as_slice()));
+        w.write_str(" {\n")
+      },
+      Elsif => {
+        try!(w.write_str("} else if "));
+        try!(w.write_str(self.content.as_slice()));
+        w.write_str(" {\n")
+      },
+      Else => w.write_str("} else {\n"),
+      For => {
+        try!(w.write_str("for "));
+        try!(w.write_str(self.content.as_slice()));
+        w.write_str(" {\n")
+      },
+      End => w.write_str("}\n"),
+      Partial => {
+        match split_call(self.content.as_slice()) {
+          Some((name, args)) => {
+            w.write_str(format!("try!({}(writer{}{}));\n", name, if args.is_empty() { "" } else { ", " }, args))
+          },
+          None => Err(IoError{kind: InvalidInput, desc: "partial block without a call", detail: Some(self.content.clone())})
+        }
       },
       _ => {
-        w.write_line(content);
+        w.write_line(content)
       }
     }
   }
@@ -0,0 +1,786 @@
+// ers - ERb-like template engine
+// Copyright (C) 2014 Franck Verrot <franck@verrot.fr>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+use std::str;
+use std::io::IoError;
+use blocks::{Block, Code, Text, Print, Raw, Debug, Partial, Declaration, Else};
+use template::Template;
+use tree::{Node, Leaf, Conditional, Loop};
+use parser::{ParseError, ParseErrorKind};
+use signature::{Signature, Structure, Param};
+use runtime::escape_html;
+use value::{Value, Null, Boolean, Number, String, List, Object, Context};
+
+/// Number of partial calls that may be nested, so a recursive partial fails
+/// instead of overflowing the stack
+pub static MAX_DEPTH: uint = 64;
+
+/// Number of items `range` may produce, so a mistyped bound fails instead of
+/// exhausting memory
+pub static MAX_RANGE: uint = 1000000;
+
+/**
+Interpreter
+*/
+pub struct Interpreter<'a> {
+  /// Template whose functions are rendered
+  priv template: &'a Template,
+
+  /// Blocks of the template nested under their control-flow tags, built
+  /// once and shared by every render
+  priv tree: Result<~[Node], ParseError>,
+
+  /// Names of the functions and structs of the template, with the index of
+  /// their declaration in its tree
+  priv functions: ~[(~str, uint)]
+}
+
+impl<'a> Interpreter<'a> {
+  /**
+    Creates an `Interpreter` rendering the functions of `template`
+    */
+  pub fn new(template: &'a Template) -> Interpreter<'a> {
+    let mut functions = ~[];
+    let tree = template.tree();
+
+    match tree {
+      Ok(ref nodes) => {
+        for (index, node) in nodes.iter().enumerate() {
+          match declaration(node).and_then(|block| declared_name(block.content.as_slice())) {
+            Some(name) => functions.push((name, index)),
+            None       => {}
+          }
+        }
+      },
+      // Reported by `nodes` when rendering
+      Err(_) => {}
+    }
+    Interpreter{template: template, tree: tree, functions: functions}
+  }
+
+  /**
+    `render` writes the first function of the template to `writer`, looking
+    its variables up in `context`
+    */
+  pub fn render(&self, context: &mut Context, writer: &mut Writer) -> Result<(), RenderError> {
+    let nodes = try!(self.nodes());
+
+    match nodes.iter().position(|node| declaration(node).is_some()) {
+      Some(index) => self.render_at(nodes, index, 0, context, writer),
+      None        => Err(RenderError{path: self.template.path.clone(), line_no: 0, column: 0, kind: UnknownFunction(~"")})
+    }
+  }
+
+  /**
+    `render_function` writes the function or struct called `name` to
+    `writer`, looking its variables up in `context`
+    */
+  pub fn render_function(&self, name: &str, context: &mut Context, writer: &mut Writer) -> Result<(), RenderError> {
+    let nodes = try!(self.nodes());
+
+    match self.find(name) {
+      Some(index) => self.render_at(nodes, index, 0, context, writer),
+      None        => Err(RenderError{path: self.template.path.clone(), line_no: 0, column: 0, kind: UnknownFunction(name.to_owned())})
+    }
+  }
+
+  /**
+    `nodes` is the tree of the template, unless its control-flow tags are
+    not balanced
+    */
+  fn nodes<'b>(&'b self) -> Result<&'b [Node], RenderError> {
+    match self.tree {
+      Ok(ref nodes)  => Ok(nodes.as_slice()),
+      Err(ref error) => {
+        Err(RenderError{path: error.path.clone(), line_no: error.line_no, column: error.column, kind: InvalidTemplate(error.kind.clone())})
+      }
+    }
+  }
+
+  /**
+    `find` returns the index in the tree of the declaration of the function
+    or struct called `name`
+    */
+  fn find(&self, name: &str) -> Option<uint> {
+    for &(ref declared, index) in self.functions.iter() {
+      if declared.as_slice() == name {
+        return Some(index);
+      }
+    }
+    None
+  }
+
+  /**
+    `render_at` renders the function declared by the node at `index` of
+    `nodes`, whose body runs until the next declaration. `depth` counts the
+    partial calls it is nested in
+    */
+  fn render_at(&self, nodes: &[Node], index: uint, depth: uint, context: &mut Context, writer: &mut Writer) -> Result<(), RenderError> {
+    let body = nodes.slice_from(index + 1);
+    let end  = body.iter().position(|node| declaration(node).is_some()).unwrap_or(body.len());
+
+    self.execute(body.slice_to(end), depth, context, writer)
+  }
+
+  /**
+    `execute` renders `nodes` to `writer`
+    */
+  fn execute(&self, nodes: &[Node], depth: uint, context: &mut Context, writer: &mut Writer) -> Result<(), RenderError> {
+    for node in nodes.iter() {
+      match *node {
+        Leaf(ref block) => try!(self.output(&**block, depth, context, writer)),
+        Conditional(ref branches, _) => {
+          for &(ref opener, ref body) in branches.iter() {
+            let taken = match opener.class {
+              Else => true,
+              _    => try!(evaluate(&try!(parse(&**opener)), context, &**opener)).is_truthy()
+            };
+            if taken {
+              try!(self.execute(body.as_slice(), depth, context, writer));
+              break;
+            }
+          }
+        },
+        Loop(ref opener, ref body, _) => {
+          let (name, items) = try!(for_loop(&**opener));
+          let items = match try!(evaluate(&items, context, &**opener)) {
+            List(items) => items,
+            other       => { return Err(RenderError::at(&**opener, TypeMismatch(format!("cannot iterate over a {}", other.type_name())))); }
+          };
+
+          for item in items.move_iter() {
+            context.push();
+            context.set(name.clone(), item);
+            let result = self.execute(body.as_slice(), depth, context, writer);
+            context.pop();
+            try!(result);
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /**
+    `output` writes what the `Text`, `Print`, `Raw`, `Debug` or `Partial`
+    `block` renders to
+    */
+  fn output(&self, block: &Block, depth: uint, context: &mut Context, writer: &mut Writer) -> Result<(), RenderError> {
+    let output = match block.class {
+      Text    => block.content.clone(),
+      Print   => escape_html(format!("{}", try!(evaluate(&try!(parse(block)), context, block)))),
+      Raw     => format!("{}", try!(evaluate(&try!(parse(block)), context, block))),
+      Debug   => escape_html(try!(evaluate(&try!(parse(block)), context, block)).debug()),
+      Partial => { return self.call(block, depth + 1, context, writer); },
+      Code    => { return Err(RenderError::at(block, UnsupportedCode(block.content.trim().to_owned()))); },
+      _       => { return Ok(()); }
+    };
+
+    writer.write_str(output.as_slice()).map_err(|error| RenderError::at(block, IoFailure(error)))
+  }
+
+  /**
+    `call` renders the function of the template a `Partial` `block` calls,
+    with a context holding its arguments, as the `depth`th nested call
+    */
+  fn call(&self, block: &Block, depth: uint, context: &mut Context, writer: &mut Writer) -> Result<(), RenderError> {
+    let (name, arguments) = match try!(parse(block)) {
+      Call(name, arguments) => (name, arguments),
+      _ => { return Err(RenderError::at(block, InvalidExpression(block.content.clone()))); }
+    };
+    if depth > MAX_DEPTH {
+      return Err(RenderError::at(block, TooDeep(name)));
+    }
+
+    let nodes = try!(self.nodes());
+    let index = match self.find(name.as_slice()) {
+      Some(index) => index,
+      None        => { return Err(RenderError::at(block, UnknownFunction(name.clone()))); }
+    };
+    let declared = match Signature::parse(declaration(&nodes[index]).unwrap().content.as_slice()) {
+      Some(signature) => signature.params,
+      None            => { return Err(RenderError::at(block, UnknownFunction(name.clone()))); }
+    };
+    // Declarations written for generic writers leave the writer out
+    let skip   = if declared.len() > 0 && is_writer(&declared[0]) { 1 } else { 0 };
+    let params = declared.slice_from(skip);
+
+    if params.len() != arguments.len() {
+      return Err(RenderError::at(block, TypeMismatch(format!("`{}` takes {} arguments", name, params.len()))));
+    }
+
+    let mut scope = Context::new();
+    for (param, argument) in params.iter().zip(arguments.iter()) {
+      let value = try!(evaluate(argument, context, block));
+      match param.name() {
+        Some(param) => { scope.set(param, value); },
+        None        => { return Err(RenderError::at(block, InvalidExpression(format!("`{}` has a parameter without a plain name", name)))); }
+      }
+    }
+    self.render_at(nodes, index, depth, &mut scope, writer)
+  }
+}
+
+/**
+  `declaration` is the block of `node` if it is a declaration
+  */
+fn declaration<'a>(node: &'a Node) -> Option<&'a Block> {
+  match *node {
+    Leaf(ref block) if block.class == Declaration => Some(&**block),
+    _                                             => None
+  }
+}
+
+/**
+  `is_writer` tells whether `param` is the `&mut Writer` a template function
+  is declared with
+  */
+fn is_writer(param: &Param) -> bool {
+  let ty : ~str = param.ty.chars().filter(|c| !c.is_whitespace()).collect();
+  ty.as_slice() == "&mutWriter"
+}
+
+/**
+  `declared_name` is the name of the function or struct a declaration
+  declares
+  */
+fn declared_name(declaration: &str) -> Option<~str> {
+  match (Structure::parse(declaration), Signature::parse(declaration)) {
+    (Some(structure), _) => Some(structure.name),
+    (_, Some(signature)) => Some(signature.name),
+    _                    => None
+  }
+}
+
+/**
+  `for_loop` reads the `name in items` content of a `For` `block`
+  */
+fn for_loop(block: &Block) -> Result<(~str, Expr), RenderError> {
+  let header = block.content.as_slice();
+  let unsupported = || RenderError::at(block, UnsupportedCode(format!("for {}", header.trim())));
+
+  match header.find_str(" in ") {
+    Some(index) => {
+      let name = header.slice_to(index).trim();
+      if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return Err(unsupported());
+      }
+      let items = try!(parse_expression(header.slice_from(index + 4), block));
+      Ok((name.to_owned(), items))
+    },
+    None => Err(unsupported())
+  }
+}
+
+/// Expressions of the interpreter's language
+enum Expr {
+  /// `42`, `"text"`, `true`
+  Literal(Value),
+
+  /// `name`
+  Variable(~str),
+
+  /// `value.name`
+  Field(~Expr, ~str),
+
+  /// `list[index]` or `object["name"]`
+  Index(~Expr, ~Expr),
+
+  /// `value.name()`
+  Method(~Expr, ~str),
+
+  /// `name(arguments)`
+  Call(~str, ~[Expr]),
+
+  /// `!value`
+  Not(~Expr),
+
+  /// `-value`
+  Negate(~Expr),
+
+  /// `left op right`
+  Binary(~str, ~Expr, ~Expr)
+}
+
+#[deriving(Eq, Clone)]
+enum Token {
+  Identifier(~str),
+  NumberToken(f64),
+  StringToken(~str),
+  Symbol(~str)
+}
+
+/// Symbols of two characters, checked before single characters
+static SYMBOLS: &'static [&'static str] = &["==", "!=", "<=", ">=", "&&", "||"];
+
+/**
+  `tokenize` cuts `input` into `Token`s
+  */
+fn tokenize(input: &str) -> Result<~[Token], ~str> {
+  let chars : ~[char] = input.chars().collect();
+  let mut tokens = ~[];
+  let mut i = 0;
+
+  while i < chars.len() {
+    let c = chars[i];
+
+    if c.is_whitespace() {
+      i += 1;
+    } else if c.is_alphabetic() || c == '_' {
+      let mut word = ~"";
+      while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+        word.push_char(chars[i]);
+        i += 1;
+      }
+      tokens.push(Identifier(word));
+    } else if c.is_digit() {
+      let mut number = ~"";
+      while i < chars.len() && (chars[i].is_digit() || (chars[i] == '.' && i + 1 < chars.len() && chars[i + 1].is_digit())) {
+        number.push_char(chars[i]);
+        i += 1;
+      }
+      tokens.push(NumberToken(from_str::<f64>(number.as_slice()).unwrap()));
+    } else if c == '"' {
+      let mut string = ~"";
+      i += 1;
+      loop {
+        if i >= chars.len() {
+          return Err(~"unterminated string");
+        }
+        match chars[i] {
+          '"'  => { i += 1; break; },
+          '\\' if i + 1 < chars.len() => {
+            string.push_char(match chars[i + 1] {
+              'n' => '\n',
+              't' => '\t',
+              c   => c
+            });
+            i += 2;
+          },
+          c => { string.push_char(c); i += 1; }
+        }
+      }
+      tokens.push(StringToken(string));
+    } else {
+      let pair : ~str = chars.slice(i, if i + 2 <= chars.len() { i + 2 } else { i + 1 }).iter().map(|&c| c).collect();
+      if SYMBOLS.iter().any(|symbol| *symbol == pair.as_slice()) {
+        tokens.push(Symbol(pair));
+        i += 2;
+      } else if "()[],.!&*+-/%<>".contains_char(c) {
+        tokens.push(Symbol(str::from_char(c)));
+        i += 1;
+      } else {
+        return Err(format!("unexpected `{}`", c));
+      }
+    }
+  }
+  Ok(tokens)
+}
+
+/**
+  `parse` reads the expression held by `block`
+  */
+fn parse(block: &Block) -> Result<Expr, RenderError> {
+  parse_expression(block.content.as_slice(), block)
+}
+
+/**
+  `parse_expression` reads the expression `input` found in `block`
+  */
+fn parse_expression(input: &str, block: &Block) -> Result<Expr, RenderError> {
+  let invalid = |message: ~str| RenderError::at(block, InvalidExpression(format!("{} in `{}`", message, input.trim())));
+
+  let tokens = match tokenize(input) {
+    Ok(tokens)   => tokens,
+    Err(message) => { return Err(invalid(message)); }
+  };
+  let mut parser = ExprParser{tokens: tokens, index: 0};
+
+  match parser.or() {
+    Ok(expr) => {
+      if parser.index < parser.tokens.len() {
+        return Err(invalid(~"unexpected tokens"));
+      }
+      Ok(expr)
+    },
+    Err(message) => Err(invalid(message))
+  }
+}
+
+/// Recursive descent parser of expressions
+struct ExprParser {
+  tokens: ~[Token],
+  index: uint
+}
+
+impl ExprParser {
+  fn peek_symbol(&self, symbol: &str) -> bool {
+    match self.tokens.get(self.index) {
+      Some(&Symbol(ref s)) => s.as_slice() == symbol,
+      _                    => false
+    }
+  }
+
+  fn eat(&mut self, symbol: &str) -> bool {
+    if self.peek_symbol(symbol) {
+      self.index += 1;
+      return true;
+    }
+    false
+  }
+
+  fn expect(&mut self, symbol: &str) -> Result<(), ~str> {
+    if self.eat(symbol) { Ok(()) } else { Err(format!("expected `{}`", symbol)) }
+  }
+
+  fn binary(&mut self, symbols: &[&str], operand: |&mut ExprParser| -> Result<Expr, ~str>) -> Result<Expr, ~str> {
+    let mut left = try!(operand(self));
+
+    loop {
+      let symbol = match symbols.iter().find(|s| self.peek_symbol(**s)) {
+        Some(s) => (*s).to_owned(),
+        None    => { return Ok(left); }
+      };
+      self.index += 1;
+      let right = try!(operand(self));
+      left = Binary(symbol, ~left, ~right);
+    }
+  }
+
+  fn or(&mut self) -> Result<Expr, ~str> {
+    self.binary(&["||"], |p| p.and())
+  }
+
+  fn and(&mut self) -> Result<Expr, ~str> {
+    self.binary(&["&&"], |p| p.comparison())
+  }
+
+  fn comparison(&mut self) -> Result<Expr, ~str> {
+    self.binary(&["==", "!=", "<=", ">=", "<", ">"], |p| p.additive())
+  }
+
+  fn additive(&mut self) -> Result<Expr, ~str> {
+    self.binary(&["+", "-"], |p| p.multiplicative())
+  }
+
+  fn multiplicative(&mut self) -> Result<Expr, ~str> {
+    self.binary(&["*", "/", "%"], |p| p.unary())
+  }
+
+  fn unary(&mut self) -> Result<Expr, ~str> {
+    if self.eat("!") {
+      return Ok(Not(~try!(self.unary())));
+    }
+    if self.eat("-") {
+      return Ok(Negate(~try!(self.unary())));
+    }
+    // References and dereferences mean nothing to the interpreter
+    if self.eat("&") || self.eat("*") {
+      return self.unary();
+    }
+    self.postfix()
+  }
+
+  fn postfix(&mut self) -> Result<Expr, ~str> {
+    let mut expr = try!(self.primary());
+
+    loop {
+      if self.eat(".") {
+        let name = match self.tokens.get(self.index) {
+          Some(&Identifier(ref name)) => name.clone(),
+          Some(&NumberToken(n))       => format!("{}", n as uint),
+          _                           => { return Err(~"expected a field name"); }
+        };
+        self.index += 1;
+
+        if self.eat("(") {
+          try!(self.expect(")"));
+          expr = Method(~expr, name);
+        } else {
+          expr = Field(~expr, name);
+        }
+      } else if self.eat("[") {
+        let index = try!(self.or());
+        try!(self.expect("]"));
+        expr = Index(~expr, ~index);
+      } else {
+        return Ok(expr);
+      }
+    }
+  }
+
+  fn primary(&mut self) -> Result<Expr, ~str> {
+    let token = match self.tokens.get(self.index) {
+      Some(token) => token.clone(),
+      None        => { return Err(~"unexpected end of expression"); }
+    };
+    self.index += 1;
+
+    match token {
+      NumberToken(n) => Ok(Literal(Number(n))),
+      StringToken(s) => Ok(Literal(String(s))),
+      Identifier(name) => {
+        match name.as_slice() {
+          "true"  => { return Ok(Literal(Boolean(true))); },
+          "false" => { return Ok(Literal(Boolean(false))); },
+          "None"  => { return Ok(Literal(Null)); },
+          _       => {}
+        }
+        if !self.eat("(") {
+          return Ok(Variable(name));
+        }
+
+        let mut arguments = ~[];
+        if !self.eat(")") {
+          loop {
+            arguments.push(try!(self.or()));
+            if self.eat(")") {
+              break;
+            }
+            try!(self.expect(","));
+          }
+        }
+        Ok(Call(name, arguments))
+      },
+      Symbol(ref s) if s.as_slice() == "(" => {
+        let expr = try!(self.or());
+        try!(self.expect(")"));
+        Ok(expr)
+      },
+      Symbol(s) => Err(format!("unexpected `{}`", s))
+    }
+  }
+}
+
+/**
+  `evaluate` computes the value of `expr` in `context`, reporting errors at
+  `block`
+  */
+fn evaluate(expr: &Expr, context: &Context, block: &Block) -> Result<Value, RenderError> {
+  value_of(expr, context).map_err(|kind| RenderError::at(block, kind))
+}
+
+/**
+  `number` extracts the number held by `value`
+  */
+fn number(value: Value, operation: &str) -> Result<f64, RenderErrorKind> {
+  match value {
+    Number(n) => Ok(n),
+    other     => Err(TypeMismatch(format!("`{}` expects numbers, found a {}", operation, other.type_name())))
+  }
+}
+
+/**
+  `value_of` computes the value of `expr` in `context`
+  */
+fn value_of(expr: &Expr, context: &Context) -> Result<Value, RenderErrorKind> {
+  match *expr {
+    Literal(ref value) => Ok(value.clone()),
+    Variable(ref name) => {
+      match context.get(name.as_slice()) {
+        Some(value) => Ok(value.clone()),
+        None        => Err(UndefinedVariable(name.clone()))
+      }
+    },
+    Field(ref object, ref name) => {
+      match try!(value_of(&**object, context)) {
+        Object(fields) => {
+          match fields.find(name) {
+            Some(value) => Ok(value.clone()),
+            None        => Err(UndefinedVariable(format!("field `{}`", name)))
+          }
+        },
+        other => Err(TypeMismatch(format!("a {} has no field `{}`", other.type_name(), name)))
+      }
+    },
+    Index(ref container, ref index) => {
+      match (try!(value_of(&**container, context)), try!(value_of(&**index, context))) {
+        (List(items), Number(n)) => {
+          match items.get(n as uint) {
+            Some(item) if n >= 0.0 => Ok(item.clone()),
+            _                      => Err(TypeMismatch(format!("index {} is out of bounds", n)))
+          }
+        },
+        (Object(fields), String(name)) => {
+          match fields.find(&name) {
+            Some(value) => Ok(value.clone()),
+            None        => Err(UndefinedVariable(format!("field `{}`", name)))
+          }
+        },
+        (container, index) => Err(TypeMismatch(format!("cannot index a {} with a {}", container.type_name(), index.type_name())))
+      }
+    },
+    Method(ref receiver, ref name) => {
+      let receiver = try!(value_of(&**receiver, context));
+      match (name.as_slice(), receiver) {
+        // Iterating or cloning does not change a value
+        ("iter", value) | ("clone", value) => Ok(value),
+        ("len", List(items))               => Ok(Number(items.len() as f64)),
+        ("len", String(s))                 => Ok(Number(s.char_len() as f64)),
+        ("is_empty", List(items))          => Ok(Boolean(items.is_empty())),
+        ("is_empty", String(s))            => Ok(Boolean(s.is_empty())),
+        ("to_str", value)                  => Ok(String(format!("{}", value))),
+        (_, value)                         => Err(TypeMismatch(format!("a {} has no method `{}`", value.type_name(), name)))
+      }
+    },
+    Call(ref name, ref arguments) => {
+      let mut values = ~[];
+      for argument in arguments.iter() {
+        values.push(try!(value_of(argument, context)));
+      }
+
+      match (name.as_slice(), values.len()) {
+        ("range", 2) => {
+          let end   = try!(number(values.pop().unwrap(), "range"));
+          let start = try!(number(values.pop().unwrap(), "range"));
+          if !(end - start <= MAX_RANGE as f64) {
+            return Err(InvalidExpression(format!("`range` produces at most {} items", MAX_RANGE)));
+          }
+          let count = if end > start { (end - start).ceil() as uint } else { 0 };
+          Ok(List(range(0, count).map(|i| Number(start + i as f64)).collect()))
+        },
+        _ => Err(InvalidExpression(format!("unknown function `{}`", name)))
+      }
+    },
+    Not(ref value) => Ok(Boolean(!try!(value_of(&**value, context)).is_truthy())),
+    Negate(ref value) => Ok(Number(-try!(number(try!(value_of(&**value, context)), "-")))),
+    Binary(ref op, ref left, ref right) => {
+      let left = try!(value_of(&**left, context));
+
+      match op.as_slice() {
+        "&&" if !left.is_truthy() => { return Ok(Boolean(false)); },
+        "||" if left.is_truthy()  => { return Ok(Boolean(true)); },
+        _ => {}
+      }
+      let right = try!(value_of(&**right, context));
+
+      match (op.as_slice(), left, right) {
+        ("&&", _, right) | ("||", _, right) => Ok(Boolean(right.is_truthy())),
+        ("==", left, right)                 => Ok(Boolean(left == right)),
+        ("!=", left, right)                 => Ok(Boolean(left != right)),
+        ("+", String(left), right)          => {
+          let mut left = left;
+          left.push_str(format!("{}", right));
+          Ok(String(left))
+        },
+        (op, String(left), String(right))   => {
+          match op {
+            "<"  => Ok(Boolean(left < right)),
+            ">"  => Ok(Boolean(left > right)),
+            "<=" => Ok(Boolean(left <= right)),
+            ">=" => Ok(Boolean(left >= right)),
+            _    => Err(TypeMismatch(format!("`{}` does not apply to strings", op)))
+          }
+        },
+        (op, left, right) => {
+          let left  = try!(number(left, op));
+          let right = try!(number(right, op));
+          match op {
+            "<"  => Ok(Boolean(left < right)),
+            ">"  => Ok(Boolean(left > right)),
+            "<=" => Ok(Boolean(left <= right)),
+            ">=" => Ok(Boolean(left >= right)),
+            "+"  => Ok(Number(left + right)),
+            "-"  => Ok(Number(left - right)),
+            "*"  => Ok(Number(left * right)),
+            "/"  => Ok(Number(left / right)),
+            _    => Ok(Number(left % right))
+          }
+        }
+      }
+    }
+  }
+}
+
+/**
+RenderErrorKind
+*/
+#[deriving(Eq, Clone)]
+pub enum RenderErrorKind {
+  /// A `<% %>` block holds code the interpreter does not understand
+  UnsupportedCode(~str),
+
+  /// An expression could not be read
+  InvalidExpression(~str),
+
+  /// A variable or field is not defined
+  UndefinedVariable(~str),
+
+  /// A value cannot be used the way the template uses it
+  TypeMismatch(~str),
+
+  /// The control-flow tags of a template built by hand are not balanced
+  InvalidTemplate(ParseErrorKind),
+
+  /// The function to render or call is not declared by the template
+  UnknownFunction(~str),
+
+  /// A partial call is nested more than `MAX_DEPTH` calls deep, most likely
+  /// by a partial calling itself
+  TooDeep(~str),
+
+  /// The output could not be written
+  IoFailure(IoError)
+}
+
+impl fmt::Show for RenderErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      UnsupportedCode(ref code)        => write!(f.buf, "the interpreter does not support `{}`", code),
+      InvalidExpression(ref message)   => write!(f.buf, "invalid expression: {}", message),
+      UndefinedVariable(ref name)      => write!(f.buf, "undefined {}", name),
+      TypeMismatch(ref message)        => write!(f.buf, "{}", message),
+      InvalidTemplate(ref kind)        => write!(f.buf, "{}", kind),
+      UnknownFunction(ref name)        => write!(f.buf, "unknown function `{}`", name),
+      TooDeep(ref name)                => write!(f.buf, "calling `{}` nests more than {} partials", name, MAX_DEPTH),
+      IoFailure(ref error)             => write!(f.buf, "{}", error)
+    }
+  }
+}
+
+/**
+RenderError
+*/
+#[deriving(Eq, Clone)]
+pub struct RenderError {
+  /// Template holding the offending block
+  path: ~str,
+
+  /// Line of the offending block
+  line_no: int,
+
+  /// Column of the offending block
+  column: int,
+
+  /// What went wrong
+  kind: RenderErrorKind
+}
+
+impl RenderError {
+  /**
+    Creates a `RenderError` pointing at the start of `block`
+    */
+  pub fn at(block: &Block, kind: RenderErrorKind) -> RenderError {
+    RenderError{path: block.path.clone(), line_no: block.pos.start.line_no, column: block.pos.start.column, kind: kind}
+  }
+}
+
+impl fmt::Show for RenderError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f.buf, "{}:{}:{}: {}", self.path, self.line_no, self.column, self.kind)
+  }
+}
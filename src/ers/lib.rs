@@ -33,10 +33,16 @@ Source code: https://github.com/franckverrot/ers
 #![crate_type = "dylib"]
 #![deny(missing_doc)]
 
-pub use template::Template;
-pub use blocks::{Block, Class, Pos};
-pub use parser::Parser;
+extern crate serialize;
+extern crate collections;
+
+pub use template::{Template, WriteOptions};
+pub use blocks::{Block, Class, Location, Pos};
+pub use parser::{Parser, ParseError};
 pub use scanner::Scanner;
+pub use syntax::Syntax;
+pub use interpreter::Interpreter;
+pub use value::{Value, Context};
 
 /// Template implementation
 pub mod template;
@@ -47,5 +53,26 @@ pub mod blocks;
 /// High-level parser
 pub mod parser;
 
+/// Nesting of blocks under control-flow tags
+pub mod tree;
+
 /// Template scanner
 pub mod scanner;
+
+/// Configurable tag delimiters
+pub mod syntax;
+
+/// Declarations split into their parts
+pub mod signature;
+
+/// Helpers called by the generated template functions
+pub mod runtime;
+
+/// Mapping of generated code back to templates
+pub mod sourcemap;
+
+/// Dynamic values templates are rendered against by the interpreter
+pub mod value;
+
+/// Rendering of templates without compiling them
+pub mod interpreter;
@@ -14,22 +14,32 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
+use std::fmt;
+use std::io::{IoError, FileNotFound};
 use std::io::fs::File;
 use std::io::BufferedReader;
 use scanner::Scanner;
+use syntax::Syntax;
 use template::Template;
-use blocks::Block;
+use blocks::{Block, Directive, Yield, Header, Comment, Declaration};
 
 mod scanner;
 mod blocks;
 
+/// Slot filled by the content of a template that is not in a named slot
+static DEFAULT_SLOT: &'static str = "content";
+
 /**
 Parser
 */
 pub struct Parser {
   /// The version of the parser. This will prevent accidental regressions if
   /// the grammar of Ers change in the future
-  version: int
+  version: int,
+
+  /// Tag delimiters and sigils templates are written with, unless they
+  /// start with a `#ers-syntax` directive
+  syntax: Syntax
 }
 
 impl Parser {
@@ -37,28 +47,350 @@ impl Parser {
     Creates a new Parser
     */
   pub fn new() -> Parser {
-    return Parser{version: 1}
+    return Parser::with_syntax(Syntax::new())
   }
 
   /**
-    `parse_path` is the parser main function that returns an `Option<Template>`
+    Creates a new Parser for templates written with `syntax`
     */
-  pub fn parse_path(&self, path: ~str) -> Option<Template> {
-    let mut blocks : ~[~Block] = ~[];
+  pub fn with_syntax(syntax: Syntax) -> Parser {
+    return Parser{version: 1, syntax: syntax}
+  }
+
+  /**
+    `parse_path` is the parser main function that returns the `Template`
+    found at `path`, or the `ParseError` that prevented reading it
+    */
+  pub fn parse_path(&self, path: ~str) -> Result<Template, ParseError> {
+    match File::open(&Path::new(path.clone())) {
+      Ok(file)   => self.parse_reader(path, &mut BufferedReader::new(file)),
+      Err(error) => Err(ParseError::io(path, error))
+    }
+  }
+
+  /**
+    `parse_reader` reads a whole template out of `reader` and parses it.
+    `name` is used as the template's path in blocks and errors
+    */
+  pub fn parse_reader(&self, name: ~str, reader: &mut Reader) -> Result<Template, ParseError> {
+    match reader.read_to_str() {
+      Ok(input)  => self.parse_str(name, input),
+      Err(error) => Err(ParseError::io(name, error))
+    }
+  }
 
-    let mut buf  = ~BufferedReader::new(File::open(&Path::new(path.clone()))) as ~Buffer;
-    let input    = buf.read_to_str().unwrap();
-    let peekable = input.chars();
+  /**
+    `parse_str` parses the template held in `source`. `name` is used as the
+    template's path in blocks and errors, and included templates are looked
+    up relative to it
+    */
+  pub fn parse_str(&self, name: ~str, source: &str) -> Result<Template, ParseError> {
+    let mut including = ~[normalize(name.clone())];
+    let blocks = try!(self.parse_blocks(name.clone(), source, &mut including));
+    let template = Template::new(name, blocks);
 
-    let mut scanner = Scanner::new(peekable, path.clone());
+    // Once includes and layouts are resolved, control-flow tags must pair up
+    try!(template.tree());
+    Ok(template)
+  }
+
+  /**
+    `parse_blocks` scans `source` and resolves its directives. `including`
+    holds the templates being included, to detect cycles
+    */
+  fn parse_blocks(&self, name: ~str, source: &str, including: &mut ~[~str]) -> Result<~[~Block], ParseError> {
+    let mut blocks : ~[~Block] = ~[];
+    let mut scanner = Scanner::with_syntax(source.chars(), name.clone(), self.syntax.clone());
 
     loop {
-      match scanner.scan() {
+      match try!(scanner.scan()) {
         None => break,
-        Some(block) => blocks.push(~block)
+        Some(block) => {
+          if block.class == Directive {
+            let included = try!(self.resolve_directive(&block, including));
+            blocks.push_all_move(included);
+          } else {
+            blocks.push(~block)
+          }
+        }
+      }
+    }
+    self.apply_layout(blocks, including)
+  }
+
+  /**
+    `resolve_directive` returns the blocks a `Directive` block stands for
+    */
+  fn resolve_directive(&self, block: &Block, including: &mut ~[~str]) -> Result<~[~Block], ParseError> {
+    let (name, argument) = match parse_directive(block.content.as_slice()) {
+      Ok(directive) => directive,
+      Err(message)  => { return Err(ParseError::at(block, InvalidDirective(message))); }
+    };
+
+    match (name.as_slice(), argument) {
+      ("include", Some(file)) => self.include(block, file, including),
+      ("yield", slot)         => {
+        let slot = slot.unwrap_or(DEFAULT_SLOT.to_owned());
+        Ok(~[~Block{class: Yield, content: slot, pos: block.pos, path: block.path.clone()}])
+      },
+      // Resolved by `apply_layout` once the whole template is known
+      ("layout", Some(_)) | ("slot", Some(_)) | ("end", None) => Ok(~[~block.clone()]),
+      ("include", _) | ("layout", _) | ("slot", _) | ("end", _) => {
+        Err(ParseError::at(block, InvalidDirective(format!("wrong arguments for `{}`", name))))
+      },
+      _ => Err(ParseError::at(block, UnknownDirective(name.clone())))
+    }
+  }
+
+  /**
+    `apply_layout` renders `blocks` through the layout they name, if any: the
+    layout's `yield`s are replaced by the matching `slot`s of `blocks`, and
+    everything outside a slot fills the `content` slot. Headers, comments and
+    the declaration are kept in front of the layout
+    */
+  fn apply_layout(&self, blocks: ~[~Block], including: &mut ~[~str]) -> Result<~[~Block], ParseError> {
+    let mut layout : Option<~Block> = None;
+    let mut opened : Option<~str>   = None;
+    let mut slot   : Option<~Block> = None;
+    let mut head   : ~[~Block] = ~[];
+    let mut slots  : ~[(~str, ~[~Block])] = ~[];
+
+    if !blocks.iter().any(|block| block.class == Directive) {
+      return Ok(blocks);
+    }
+
+    for block in blocks.move_iter() {
+      match block.class {
+        Header | Comment | Declaration => { head.push(block); },
+        Directive => {
+          let (name, argument) = parse_directive(block.content.as_slice()).unwrap();
+          match name.as_slice() {
+            "layout" if layout.is_some() => {
+              return Err(ParseError::at(&*block, InvalidDirective(~"a template can only have one layout")));
+            },
+            "layout" => { layout = Some(block); },
+            "slot" if opened.is_some() => {
+              return Err(ParseError::at(&*block, InvalidDirective(~"slots cannot be nested")));
+            },
+            "slot" => { opened = argument; slot = Some(block); },
+            _ if opened.is_none() => {
+              return Err(ParseError::at(&*block, InvalidDirective(~"`end` without `slot`")));
+            },
+            _ => { opened = None; }
+          }
+        },
+        _ => {
+          let name = opened.clone().unwrap_or(DEFAULT_SLOT.to_owned());
+          match slots.iter().position(|&(ref filled, _)| *filled == name) {
+            Some(index) => slots[index].mut1().push(block),
+            None        => slots.push((name, ~[block]))
+          }
+        }
+      }
+    }
+
+    if opened.is_some() {
+      return Err(ParseError::at(&*slot.unwrap(), InvalidDirective(~"`slot` is never closed by `end`")));
+    }
+
+    let layout = match layout {
+      Some(layout) => layout,
+      None => {
+        let block = slot.unwrap();
+        return Err(ParseError::at(&*block, InvalidDirective(~"`slot` is only allowed in templates using a layout")));
       }
+    };
+
+    if head.iter().count(|block| block.class == Declaration) > 1 {
+      return Err(ParseError::at(&*layout, InvalidDirective(~"templates using a layout can only declare one function")));
+    }
+
+    let (_, file) = parse_directive(layout.content.as_slice()).unwrap();
+    for block in try!(self.include(&*layout, file.unwrap(), including)).move_iter() {
+      match block.class {
+        Declaration => {
+          return Err(ParseError::at(&*block, InvalidDirective(~"layouts cannot declare functions")));
+        },
+        Yield => {
+          match slots.iter().find(|&&(ref filled, _)| *filled == block.content) {
+            Some(&(_, ref content)) => head.push_all(content.as_slice()),
+            // Left for the layouts this one extends, writes nothing otherwise
+            None                    => head.push(block)
+          }
+        },
+        _ => { head.push(block); }
+      }
+    }
+    Ok(head)
+  }
+
+  /**
+    `include` parses the template at `file`, relative to the template holding
+    the `include` or `layout` directive `block`
+    */
+  fn include(&self, block: &Block, file: ~str, including: &mut ~[~str]) -> Result<~[~Block], ParseError> {
+    let path = normalize(Path::new(block.path.clone()).dir_path().join(file).display().to_str());
+
+    if including.contains(&path) {
+      return Err(ParseError::at(block, IncludeCycle(path)));
+    }
+
+    let source = match File::open(&Path::new(path.clone())) {
+      Ok(file) => {
+        match BufferedReader::new(file).read_to_str() {
+          Ok(source) => source,
+          Err(error) => { return Err(ParseError::io(path, error)); }
+        }
+      },
+      Err(ref error) if error.kind == FileNotFound => { return Err(ParseError::at(block, IncludeNotFound(path))); },
+      Err(error) => { return Err(ParseError::io(path, error)); }
+    };
+
+    including.push(path.clone());
+    let blocks = self.parse_blocks(path, source, including);
+    including.pop();
+    blocks
+  }
+}
+
+/**
+  `normalize` cleans up `path` so the same template is always referred to
+  by the same name
+  */
+fn normalize(path: ~str) -> ~str {
+  Path::new(path).display().to_str()
+}
+
+/**
+  `parse_directive` splits the content of a `Directive` block into the
+  directive's name and its optional quoted argument
+  */
+pub fn parse_directive(content: &str) -> Result<(~str, Option<~str>), ~str> {
+  let content = content.trim();
+  let (name, rest) = match content.find(|c: char| c.is_whitespace()) {
+    Some(index) => (content.slice_to(index), content.slice_from(index).trim()),
+    None        => (content, "")
+  };
+
+  if name.is_empty() {
+    return Err(~"missing directive name");
+  }
+  if rest.is_empty() {
+    return Ok((name.to_owned(), None));
+  }
+  if rest.len() < 2 || !rest.starts_with("\"") || !rest.ends_with("\"") {
+    return Err(format!("expected a quoted argument, found `{}`", rest));
+  }
+  Ok((name.to_owned(), Some(rest.slice(1, rest.len() - 1).to_owned())))
+}
+
+/**
+ParseErrorKind
+*/
+#[deriving(Eq, Clone)]
+pub enum ParseErrorKind {
+  /// A `<%`, `<%!`, `<%=`, `<%==`, `<%?` or `<%#` block reached the end of
+  /// the input before `%>`
+  UnterminatedCodeBlock(~str),
+
+  /// A `<%%` block reached the end of the input before the `%%>` it holds
+  UnterminatedHeader(~str),
+
+  /// A `<%%` block spans more than one line
+  NewlineInHeader,
+
+  /// A `<%` is followed by a sigil ers reserves but the `Syntax` does not use
+  UnknownTag(char),
+
+  /// A `<%>` block does not hold a function call, like `user_card(&user)`
+  InvalidPartial(~str),
+
+  /// The `#ers-syntax` first line could not be understood
+  InvalidSyntaxDirective(~str),
+
+  /// The `Syntax` given to the parser makes tags ambiguous
+  InvalidSyntax(~str),
+
+  /// A `<%@` directive is not known to ers
+  UnknownDirective(~str),
+
+  /// A `<%@` directive is malformed
+  InvalidDirective(~str),
+
+  /// An included template does not exist
+  IncludeNotFound(~str),
+
+  /// A template includes itself, directly or not
+  IncludeCycle(~str),
+
+  /// An `if` or `for` tag is not closed by `end`, or an `elsif`, `else` or
+  /// `end` tag does not belong to one
+  UnbalancedBlock(~str),
+
+  /// The template could not be read
+  IoFailure(IoError)
+}
+
+impl fmt::Show for ParseErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      UnterminatedCodeBlock(ref close)    => write!(f.buf, "unterminated block, expected `{}`", close),
+      UnterminatedHeader(ref close)       => write!(f.buf, "unterminated header, expected `{}`", close),
+      NewlineInHeader                     => write!(f.buf, "headers must fit on a single line"),
+      UnknownTag(c)                       => write!(f.buf, "unknown tag sigil `{}`", c),
+      InvalidPartial(ref call)            => write!(f.buf, "expected a call like `name(args)`, found `{}`", call),
+      InvalidSyntaxDirective(ref message) => write!(f.buf, "invalid syntax directive: {}", message),
+      InvalidSyntax(ref message)          => write!(f.buf, "invalid syntax: {}", message),
+      UnknownDirective(ref name)          => write!(f.buf, "unknown directive `{}`", name),
+      InvalidDirective(ref message)       => write!(f.buf, "invalid directive: {}", message),
+      IncludeNotFound(ref path)           => write!(f.buf, "included template `{}` not found", path),
+      IncludeCycle(ref path)              => write!(f.buf, "`{}` ends up including itself", path),
+      UnbalancedBlock(ref message)        => write!(f.buf, "unbalanced block: {}", message),
+      IoFailure(ref error)                => write!(f.buf, "{}", error)
+    }
+  }
+}
+
+/**
+ParseError
+*/
+#[deriving(Eq, Clone)]
+pub struct ParseError {
+  /// Location of the template that failed to parse
+  path: ~str,
+
+  /// Line of the `<%` that opened the offending block
+  line_no: int,
+
+  /// Column of the `<%` that opened the offending block
+  column: int,
+
+  /// What went wrong
+  kind: ParseErrorKind
+}
+
+impl ParseError {
+  /**
+    Creates a `ParseError` for a template that could not be read at all
+    */
+  pub fn io(path: ~str, error: IoError) -> ParseError {
+    ParseError{path: path, line_no: 0, column: 0, kind: IoFailure(error)}
+  }
+
+  /**
+    Creates a `ParseError` pointing at the start of `block`
+    */
+  pub fn at(block: &Block, kind: ParseErrorKind) -> ParseError {
+    ParseError{path: block.path.clone(), line_no: block.pos.start.line_no, column: block.pos.start.column, kind: kind}
+  }
+}
+
+impl fmt::Show for ParseError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.kind {
+      IoFailure(_) => write!(f.buf, "{}: {}", self.path, self.kind),
+      _            => write!(f.buf, "{}:{}:{}: {}", self.path, self.line_no, self.column, self.kind)
     }
-    Some(Template{path: path.clone(), blocks: blocks})
   }
 }
 
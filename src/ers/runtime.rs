@@ -0,0 +1,63 @@
+// ers - ERb-like template engine
+// Copyright (C) 2014 Franck Verrot <franck@verrot.fr>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+use std::cmp;
+use std::sync::atomics::{AtomicUint, Relaxed};
+
+/**
+  `escape_html` returns a copy of `input` where every character that is
+  significant in HTML is replaced by its entity
+  */
+pub fn escape_html(input: &str) -> ~str {
+  let mut output = ~"";
+
+  for c in input.chars() {
+    match c {
+      '&'  => output.push_str("&amp;"),
+      '<'  => output.push_str("&lt;"),
+      '>'  => output.push_str("&gt;"),
+      '"'  => output.push_str("&quot;"),
+      '\'' => output.push_str("&#39;"),
+      _    => output.push_char(c)
+    }
+  }
+  output
+}
+
+/**
+  `size_hint` is the capacity to reserve before rendering a template whose
+  static text is `static_len` bytes long, given the size `learned` from
+  previous renders
+  */
+pub fn size_hint(static_len: uint, learned: &AtomicUint) -> uint {
+  cmp::max(static_len, learned.load(Relaxed))
+}
+
+/**
+  `learn_size` records that a render produced `len` bytes. `learned` moves a
+  quarter of the way towards `len`, so one unusual page does not throw the
+  estimate off
+  */
+pub fn learn_size(learned: &mut AtomicUint, len: uint) {
+  let previous = learned.load(Relaxed);
+
+  if previous == 0 {
+    learned.store(len, Relaxed);
+  } else {
+    learned.store(previous - previous / 4 + len / 4, Relaxed);
+  }
+}
@@ -14,17 +14,24 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use std::iter::Peekable;
-use blocks::{Block, Pos};
+use blocks::{Block, Class, Location, Pos};
+use parser::{ParseError, ParseErrorKind};
+use parser::{UnterminatedCodeBlock, UnterminatedHeader, NewlineInHeader, UnknownTag};
+use parser::{InvalidSyntaxDirective, InvalidSyntax, InvalidPartial};
+use syntax::{Syntax, DIRECTIVE};
 
 mod blocks;
 
+/// Characters that may not directly follow the opening of a tag unless the
+/// active `Syntax` uses them as sigils, as ers keeps them for its tags
+static RESERVED_SIGILS: &'static [char] = &['!', '%', '=', '?', '#', '@', '>'];
+
 /**
 Scanner
 */
 pub struct Scanner<Stream> {
   /// Internal buffer used to parse the content of the buffer
-  buffer:  Peekable<char, Stream>,
+  buffer:  Stream,
 
   /// Location of the template to be parsed on the local filesystem
   path:    ~str,
@@ -32,8 +39,20 @@ pub struct Scanner<Stream> {
   /// Last block position parsed by the scanner
   pos:     Pos,
 
-  /// Last line parsed by the scanner
-  priv current_line: int,
+  /// Tag delimiters and sigils recognized by the scanner
+  syntax:  Syntax,
+
+  /// Location of the next character to be read
+  priv current: Location,
+
+  /// Location where the block being scanned started
+  priv block_start: Location,
+
+  /// Characters pulled out of `buffer` by `peek_nth` but not read yet
+  priv lookahead: ~[char],
+
+  /// Whether the first line was checked for a `#ers-syntax` directive
+  priv started: bool,
 }
 
 impl <Stream : Iterator<char>> Scanner<Stream> {
@@ -41,11 +60,23 @@ impl <Stream : Iterator<char>> Scanner<Stream> {
     `new` build a `Scanner` object from a `Stream` and a `path`
    */
   pub fn new(input: Stream, path: ~str) -> Scanner<Stream> {
+    Scanner::with_syntax(input, path, Syntax::new())
+  }
+
+  /**
+    `with_syntax` build a `Scanner` object from a `Stream` and a `path`,
+    recognizing tags written with `syntax`
+   */
+  pub fn with_syntax(input: Stream, path: ~str, syntax: Syntax) -> Scanner<Stream> {
     Scanner {
-      buffer: input.peekable(),
+      buffer: input,
       path: path.clone(),
-      pos: Pos{line_no: 1},
-      current_line: 1
+      pos: Pos::new(Location::new(1, 1, 0), Location::new(1, 1, 0)),
+      syntax: syntax,
+      current: Location::new(1, 1, 0),
+      block_start: Location::new(1, 1, 0),
+      lookahead: ~[],
+      started: false
     }
   }
 
@@ -54,269 +85,435 @@ impl <Stream : Iterator<char>> Scanner<Stream> {
     to be read
    */
   pub fn read_char(&mut self) -> Option<char> {
-    match self.buffer.next() {
+    let next = if self.lookahead.is_empty() { self.buffer.next() } else { self.lookahead.shift() };
+
+    match next {
       None => None,
       Some(character) => {
-        if character == '\n' { self.current_line += 1; }
+        if character == '\n' {
+          self.current.line_no += 1;
+          self.current.column = 1;
+        } else {
+          self.current.column += 1;
+        }
+        self.current.offset += character.len_utf8_bytes();
         Some(character)
       }
     }
   }
 
+  /**
+    `skip` is an utility method that consumes the next `n` characters
+   */
+  pub fn skip(&mut self, n: uint) {
+    for _ in range(0, n) {
+      self.read_char();
+    }
+  }
+
   /**
     `peek_char` is an utility method that inspects the next character
     to be read
    */
   pub fn peek_char(&mut self) -> Option<char> {
-    match self.buffer.peek() {
-      None    => None,
-      Some(c) => { Some(*c) }
+    self.peek_nth(0)
+  }
+
+  /**
+    `peek_nth` inspects the character `n` positions after the next one to be
+    read, without consuming anything
+   */
+  pub fn peek_nth(&mut self, n: uint) -> Option<char> {
+    while self.lookahead.len() <= n {
+      match self.buffer.next() {
+        None    => { return None; },
+        Some(c) => { self.lookahead.push(c); }
+      }
     }
+    Some(self.lookahead[n])
   }
 
   /**
-    `scan` is the entry point of a `Scanner`
+    `looking_at` tells whether `expected` starts `n` characters after the
+    next one to be read, without consuming anything
    */
-  pub fn scan(&mut self) -> Option<Block> {
-    let c = self.read_char();
-
-    match c {
-      None => { None },
-      Some(c) => {
-        let s = ::std::str::from_char(c);
-        if ::std::str::eq(&s,&~"<") {
-          return self.scanBlock()
-        }
-        else {
-          return self.scanTextBlock(s)
-        }
+  pub fn looking_at(&mut self, n: uint, expected: &str) -> bool {
+    for (i, c) in expected.chars().enumerate() {
+      if self.peek_nth(n + i) != Some(c) {
+        return false;
       }
     }
+    true
   }
 
   /**
-    `scanBlock` is the entry point of the block parsing strategy
+    `swallow_newline` consumes the next line ending, if any
    */
-  pub fn scanBlock(&mut self) -> Option<Block> {
-    let c = self.read_char();
-
-    match c {
-      None => {
-        return Some(Block{class: ::blocks::Text, content: ~"<", pos: Pos{line_no: self.current_line }});
-      },
-      Some(c) => {
-        let s = ::std::str::from_char(c);
-
-        if ::std::str::eq(&s,&~"%") {
-          return self.scanCodeBlock();
-        }
-        else {
-          return self.scanTextBlock(format!("<{:s}", s));
+  pub fn swallow_newline(&mut self) {
+    if self.peek_char() == Some('\r') && self.peek_nth(1) == Some('\n') {
+      self.read_char();
+    }
+    if self.peek_char() == Some('\n') {
+      self.read_char();
+    }
+  }
+
+  /**
+    `error` builds a `ParseError` pointing at the block being scanned
+   */
+  pub fn error(&self, kind: ParseErrorKind) -> ParseError {
+    ParseError {
+      path: self.path.clone(),
+      line_no: self.block_start.line_no,
+      column: self.block_start.column,
+      kind: kind
+    }
+  }
+
+  /**
+    `block` builds a `Block` spanning from the start of the block being
+    scanned to the current location
+   */
+  pub fn block(&mut self, class: Class, content: ~str) -> Block {
+    self.pos = Pos::new(self.block_start, self.current);
+    Block{class: class, content: content, pos: self.pos, path: self.path.clone()}
+  }
+
+  /**
+    `scan` is the entry point of a `Scanner`. It returns `Ok(None)` once the
+    input is exhausted
+   */
+  pub fn scan(&mut self) -> Result<Option<Block>, ParseError> {
+    if !self.started {
+      self.started = true;
+      match self.syntax.validate() {
+        Ok(())       => {},
+        Err(message) => { return Err(self.error(InvalidSyntax(message))); }
+      }
+      try!(self.scanSyntaxDirective());
+    }
+
+    loop {
+      self.block_start = self.current;
+
+      let open = self.syntax.open.char_at(0);
+      let block = match self.peek_char() {
+        None => { return Ok(None) },
+        Some(c) if c == open => {
+          self.read_char();
+          try!(self.scanBlock())
+        },
+        Some(_) => {
+          try!(self.scanTextBlock(~""))
         }
+      };
+
+      // Text left empty by a trimming tag is not worth a block
+      if block.class != ::blocks::Text || !block.content.is_empty() {
+        return Ok(Some(block));
       }
     }
   }
 
   /**
-    `scanCodeBlock` will parse `Code` blocks
+    `scanSyntaxDirective` replaces the scanner's `Syntax` when the template
+    starts with a `#ers-syntax` line, and consumes that line
    */
-  pub fn scanCodeBlock(&mut self) -> Option<Block> {
-    let c = self.read_char();
-
-    match c {
-      None => { None },
-      Some(c) => {
-        let s = ::std::str::from_char(c);
-        if ::std::str::eq(&s,&~"!") {
-          return self.scanDeclarationBlock();
-        }
-        else if ::std::str::eq(&s,&~"%") {
-          return self.scanHeaderBlock();
-        }
-        else if ::std::str::eq(&s,&~"=") {
-          return self.scanPrintBlock();
-        } else {
-          match self.scanContent(~"") {
-            None    => None,
-            Some(ctn) => Some(Block{class: ::blocks::Code, content: format!("{:s}{:s}", s, ctn), pos: Pos{line_no: self.current_line}})
-          }
-        }
+  pub fn scanSyntaxDirective(&mut self) -> Result<(), ParseError> {
+    if !self.looking_at(0, DIRECTIVE) {
+      return Ok(());
+    }
+    self.skip(DIRECTIVE.char_len());
+
+    let mut settings = ~"";
+    loop {
+      match self.read_char() {
+        None | Some('\n') => break,
+        Some(c) => settings.push_char(c)
       }
     }
+
+    match Syntax::from_directive(settings) {
+      Ok(syntax)   => { self.syntax = syntax; Ok(()) },
+      Err(message) => Err(self.error(InvalidSyntaxDirective(message)))
+    }
   }
 
   /**
-    `scanDeclarationBlock` will parse `Declaration` blocks
+    `scanBlock` is the entry point of the block parsing strategy, called
+    once the first character of the opening tag was read
    */
-  pub fn scanDeclarationBlock(&mut self) -> Option<Block> {
-    let mut output = ~"";
-    match self.scanContent(~"") {
-      None => { return None; },
-      Some(s) => { output.push_str(s); }
+  pub fn scanBlock(&mut self) -> Result<Block, ParseError> {
+    let open  = self.syntax.open.clone();
+    let first = open.char_at(0);
+    let rest  = open.slice_from(first.len_utf8_bytes());
+
+    if self.looking_at(0, rest) {
+      self.skip(rest.char_len());
+      return self.scanCodeBlock();
     }
-    return Some(Block{class: ::blocks::Declaration, content: output, pos: Pos{line_no: self.current_line}});
+    return self.scanTextBlock(::std::str::from_char(first));
   }
 
   /**
-    `scanHeaderBlock` will parse `Header` blocks
+    `scanCodeBlock` will parse `Code` blocks, and the control-flow tags
+    `if`, `elsif`, `else`, `for` and `end`
    */
-  pub fn scanHeaderBlock(&mut self) -> Option<Block> {
-    let mut output = ~"";
-    match self.scanHeaderContent(~"") {
-      None => { return None; },
-      Some(s) => { output.push_str(s); }
+  pub fn scanCodeBlock(&mut self) -> Result<Block, ParseError> {
+    // The indentation before a trimming tag was already removed by
+    // `scanTextBlock`
+    if self.peek_char() == Some(self.syntax.trim) {
+      self.read_char();
+    }
+
+    let c = match self.peek_char() {
+      None    => { return Err(self.error(UnterminatedCodeBlock(self.syntax.close.clone()))); },
+      Some(c) => c
+    };
+
+    if c == self.syntax.declaration {
+      self.read_char();
+      return self.scanDeclarationBlock();
+    }
+    else if c == self.syntax.header {
+      self.read_char();
+      return self.scanHeaderBlock();
+    }
+    else if c == self.syntax.print {
+      self.read_char();
+      if self.peek_char() == Some(self.syntax.print) {
+        self.read_char();
+        return self.scanRawBlock();
+      }
+      return self.scanPrintBlock();
+    }
+    else if c == self.syntax.debug {
+      self.read_char();
+      return self.scanDebugBlock();
+    }
+    else if c == self.syntax.comment {
+      self.read_char();
+      return self.scanCommentBlock();
+    }
+    else if c == self.syntax.directive {
+      self.read_char();
+      return self.scanDirectiveBlock();
+    }
+    else if c == self.syntax.partial {
+      self.read_char();
+      return self.scanPartialBlock();
+    }
+    else if RESERVED_SIGILS.contains(&c) {
+      return Err(self.error(UnknownTag(c)));
+    } else {
+      let ctn = try!(self.scanContent(~""));
+      let tag = control_tag(ctn.as_slice());
+
+      match tag {
+        Some((class, content)) => Ok(self.block(class, content)),
+        None                   => Ok(self.block(::blocks::Code, ctn))
+      }
     }
-    return Some(Block{class: ::blocks::Header, content: output, pos: Pos{line_no: self.current_line}});
+  }
+
+  /**
+    `scanDeclarationBlock` will parse `Declaration` blocks
+   */
+  pub fn scanDeclarationBlock(&mut self) -> Result<Block, ParseError> {
+    let output = try!(self.scanContent(~""));
+    return Ok(self.block(::blocks::Declaration, output));
+  }
+
+  /**
+    `scanHeaderBlock` will parse `Header` blocks
+   */
+  pub fn scanHeaderBlock(&mut self) -> Result<Block, ParseError> {
+    let output = try!(self.scanHeaderContent(~""));
+    return Ok(self.block(::blocks::Header, output));
   }
 
   /**
     `scanPrintBlock` will parse `Print` blocks
    */
-  pub fn scanPrintBlock(&mut self) -> Option<Block> {
-    let mut output = ~"";
-    match self.scanContent(~"") {
-      None => { return None; },
-      Some(s) => { output.push_str(s); }
+  pub fn scanPrintBlock(&mut self) -> Result<Block, ParseError> {
+    let output = try!(self.scanContent(~""));
+    return Ok(self.block(::blocks::Print, output));
+  }
+
+  /**
+    `scanRawBlock` will parse `Raw` blocks
+   */
+  pub fn scanRawBlock(&mut self) -> Result<Block, ParseError> {
+    let output = try!(self.scanContent(~""));
+    return Ok(self.block(::blocks::Raw, output));
+  }
+
+  /**
+    `scanDebugBlock` will parse `Debug` blocks
+   */
+  pub fn scanDebugBlock(&mut self) -> Result<Block, ParseError> {
+    let output = try!(self.scanContent(~""));
+    return Ok(self.block(::blocks::Debug, output));
+  }
+
+  /**
+    `scanCommentBlock` will parse `Comment` blocks
+   */
+  pub fn scanCommentBlock(&mut self) -> Result<Block, ParseError> {
+    let output = try!(self.scanContent(~""));
+    return Ok(self.block(::blocks::Comment, output));
+  }
+
+  /**
+    `scanPartialBlock` will parse `Partial` blocks, which must hold a call
+    like `user_card(&user)`
+   */
+  pub fn scanPartialBlock(&mut self) -> Result<Block, ParseError> {
+    let output = try!(self.scanContent(~""));
+    let call   = output.trim().to_owned();
+
+    if ::blocks::split_call(call.as_slice()).is_some() {
+      Ok(self.block(::blocks::Partial, call))
+    } else {
+      Err(self.error(InvalidPartial(call)))
     }
-    return Some(Block{class: ::blocks::Print, content: output, pos: Pos{line_no: self.current_line}});
   }
 
   /**
-    `scanTextBlock` will parse `Text` blocks
+    `scanDirectiveBlock` will parse `Directive` blocks
    */
-  pub fn scanTextBlock(&mut self, s: ~str) -> Option<Block> {
+  pub fn scanDirectiveBlock(&mut self) -> Result<Block, ParseError> {
+    let output = try!(self.scanContent(~""));
+    return Ok(self.block(::blocks::Directive, output));
+  }
+
+  /**
+    `scanTextBlock` will parse `Text` blocks. A backslash followed by the
+    opening tag, e.g. `\<%`, is read as a literal opening tag, and two
+    backslashes followed by the opening tag, e.g. `\\<%`, as a backslash
+    before a live tag
+   */
+  pub fn scanTextBlock(&mut self, s: ~str) -> Result<Block, ParseError> {
     let mut output = s.clone();
+    let open       = self.syntax.open.clone();
+    let first      = open.char_at(0);
+
     loop {
       let c = self.peek_char();
       match c {
         None => {
           break;
         },
-        Some(c) => {
-          let s = ::std::str::from_char(c);
-          if ::std::str::eq(&s,&~"<") {
-            //self.buffer.push_bytes(&mut b,1);
-            break;
-          }
-          else {
-            let c = self.read_char().unwrap();
-            output.push_char(c);
+        Some('\\') if self.looking_at(1, "\\") && self.looking_at(2, open.as_slice()) => {
+          self.skip(2);
+          output.push_char('\\');
+        },
+        Some('\\') if self.looking_at(1, open.as_slice()) => {
+          self.skip(1 + open.char_len());
+          output.push_str(open);
+        },
+        Some(c) if c == first => {
+          if self.looking_at(0, open.as_slice()) && self.peek_nth(open.char_len()) == Some(self.syntax.trim) {
+            self.trim_indentation(&mut output);
           }
+          break;
+        },
+        Some(c) => {
+          self.read_char();
+          output.push_char(c);
         }
       }
     }
-    return Some(Block{class: ::blocks::Text, content: output, pos: Pos{line_no: self.current_line}});
+    return Ok(self.block(::blocks::Text, output));
   }
 
   /**
-    `scanContent` will parse any text section
+    `trim_indentation` removes the spaces and tabs ending `output` when they
+    are the only thing on their line
    */
-  pub fn scanContent(&mut self, prefix: ~str) -> Option<~str> {
-    let mut output = prefix.clone();
+  fn trim_indentation(&self, output: &mut ~str) {
+    let line_start = match output.rfind('\n') {
+      Some(index) => index + 1,
+      None if self.block_start.column == 1 => 0,
+      None => { return; }
+    };
 
-    match self.read_char() {
-      None => {
-        return None;
-      },
-      Some(val) => {
-        let s = ::std::str::from_char(val);
-        output.push_str(s);
-
-        loop {
-          let c = self.read_char();
-          match c {
-            None => {
-              return None;
-            },
-            Some(c) => {
-              let s = ::std::str::from_char(c);
-              if ::std::str::eq(&s,&~"%") {
-                let inner_c = self.read_char();
-                match inner_c {
-                  None => { return None; },
-                  Some(inner_c) => {
-                    let inner_s = ::std::str::from_char(inner_c);
-                    if ::std::str::eq(&inner_s,&~">") {
-                      break;
-                    }
-                    else {
-                      output.push_char('%');
-                      output.push_char(inner_c);
-                    }
-                  }
-                }
-              }
-              else {
-                output.push_char(c);
-              }
-            }
-          }
-        }
-        return Some(output);
-      }
+    if output.slice_from(line_start).chars().all(|c| c == ' ' || c == '\t') {
+      output.truncate(line_start);
     }
   }
 
   /**
-    `scanHeaderContent` will parse `Header` blocks
+    `scanContent` will parse any text section up to the closing tag, or up
+    to a trimming closing tag (e.g. `-%>`) which also swallows the following
+    newline
    */
-  pub fn scanHeaderContent(&mut self, prefix: ~str) -> Option<~str> {
+  pub fn scanContent(&mut self, prefix: ~str) -> Result<~str, ParseError> {
+    let close = self.syntax.close.clone();
+    self.scanUntil(prefix, close.clone(), UnterminatedCodeBlock(close), false)
+  }
+
+  /**
+    `scanHeaderContent` will parse `Header` blocks up to the closing tag
+    (e.g. `%%>`), or up to a trimming closing tag (e.g. `-%%>`) which also
+    swallows the following newline. Headers must fit on a single line.
+   */
+  pub fn scanHeaderContent(&mut self, prefix: ~str) -> Result<~str, ParseError> {
+    let close = self.syntax.header_close();
+    self.scanUntil(prefix, close.clone(), UnterminatedHeader(close), true)
+  }
+
+  /**
+    `scanUntil` accumulates characters until `close` is found, failing with
+    `unterminated` at the end of the input
+   */
+  fn scanUntil(&mut self, prefix: ~str, close: ~str, unterminated: ParseErrorKind, single_line: bool) -> Result<~str, ParseError> {
     let mut output = prefix.clone();
+    let trim       = self.syntax.trim;
 
-    match self.read_char() {
-      None => { return None; },
-      Some(val) => {
-        let s = ::std::str::from_char(val);
-        output.push_str(s);
-
-        loop {
-          let c = self.read_char();
-          match c {
-            None => { return None; },
-            Some(c) => {
-              let s = ::std::str::from_char(c);
-              if ::std::str::eq(&s,&~"%") {
-                let inner_c = self.read_char();
-                match inner_c {
-                  None => { return None; },
-                  Some(inner_c) => {
-
-                    let s = ::std::str::from_char(c);
-                    if ::std::str::eq(&s,&~"%") {
-                      let inner_c = self.read_char();
-                      match inner_c {
-                        None => { return None; },
-                        Some(inner_c) => {
-                          let inner_s = ::std::str::from_char(inner_c);
-                          if ::std::str::eq(&inner_s,&~">") {
-                            break;
-                          } else if ::std::str::eq(&inner_s,&~"\n") {
-                            return None;
-                          } else {
-                            output.push_char('%');
-                            output.push_char('%');
-                            output.push_char(inner_c);
-                          }
-                        }
-                      }
-                    } else if ::std::str::eq(&s,&~"\n") {
-                      return None;
-                    }
-                    else {
-                      output.push_char('%');
-                      output.push_char(inner_c);
-                    }
-                  }
-                }
-              }
-              else {
-                output.push_char(c);
-              }
-            }
-          }
-        }
-        return Some(output);
+    loop {
+      if self.peek_char() == Some(trim) && self.looking_at(1, close.as_slice()) {
+        self.skip(1 + close.char_len());
+        self.swallow_newline();
+        break;
+      }
+      if self.looking_at(0, close.as_slice()) {
+        self.skip(close.char_len());
+        break;
+      }
+
+      match self.read_char() {
+        None                      => { return Err(self.error(unterminated)); },
+        Some('\n') if single_line => { return Err(self.error(NewlineInHeader)); },
+        Some(c)                   => { output.push_char(c); }
       }
     }
+    return Ok(output);
+  }
+}
+
+/**
+  `control_tag` returns the class and content of the block a `<% %>` tag
+  holding `code` stands for, unless it holds plain Rust code. Code with a
+  brace anywhere is plain Rust, like `if x {` or `if debug { log() }`
+  */
+fn control_tag(code: &str) -> Option<(Class, ~str)> {
+  let code = code.trim();
+  let argument = |keyword: &str| code.slice_from(keyword.len()).trim().to_owned();
+
+  if code == "else" {
+    Some((::blocks::Else, ~""))
+  } else if code == "end" {
+    Some((::blocks::End, ~""))
+  } else if code.contains_char('{') || code.contains_char('}') {
+    None
+  } else if code.starts_with("if ") {
+    Some((::blocks::If, argument("if ")))
+  } else if code.starts_with("elsif ") {
+    Some((::blocks::Elsif, argument("elsif ")))
+  } else if code.starts_with("for ") && code.contains(" in ") {
+    Some((::blocks::For, argument("for ")))
+  } else {
+    None
   }
 }
@@ -0,0 +1,392 @@
+// ers - ERb-like template engine
+// Copyright (C) 2014 Franck Verrot <franck@verrot.fr>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+
+/// Type parameter of the writer ers injects in generic template functions
+pub static WRITER_TYPE: &'static str = "ErsWriter";
+
+/**
+Param
+*/
+#[deriving(Eq, Clone)]
+pub struct Param {
+  /// Pattern binding the argument, e.g. `user`
+  pattern: ~str,
+
+  /// Type of the argument, e.g. `&User`
+  ty: ~str
+}
+
+impl Param {
+  /**
+    `name` is the identifier bound by the parameter, if its pattern is a
+    plain, possibly `mut`, identifier
+    */
+  pub fn name(&self) -> Option<~str> {
+    let name = self.pattern.trim();
+    let name = if name.starts_with("mut ") { name.slice_from(4).trim() } else { name };
+
+    if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+      Some(name.to_owned())
+    } else {
+      None
+    }
+  }
+}
+
+/**
+Signature
+*/
+#[deriving(Eq, Clone)]
+pub struct Signature {
+  /// Everything up to the function's name, e.g. `pub fn`
+  prefix: ~str,
+
+  /// Name of the function
+  name: ~str,
+
+  /// Generic parameters including their brackets, e.g. `<T: Show>`, or an
+  /// empty string
+  generics: ~str,
+
+  /// Parameters of the function, in order
+  params: ~[Param],
+
+  /// Return type of the function, if it names one
+  output: Option<~str>
+}
+
+impl Signature {
+  /**
+    `parse` splits the content of a `Declaration` block, like
+    `pub fn Page(writer: &mut Writer, name: &str)`, into a `Signature`
+    */
+  pub fn parse(declaration: &str) -> Option<Signature> {
+    let declaration = declaration.trim();
+
+    let start = if declaration.starts_with("fn ") {
+      0
+    } else {
+      match declaration.find_str(" fn ") {
+        Some(index) => index + 1,
+        None        => { return None; }
+      }
+    };
+    let prefix = declaration.slice_to(start + 2).to_owned();
+    let rest   = declaration.slice_from(start + 2).trim_left();
+
+    let name_end = match rest.find(|c: char| !(c.is_alphanumeric() || c == '_')) {
+      Some(index) => index,
+      None        => { return None; }
+    };
+    let name = rest.slice_to(name_end).to_owned();
+    let mut rest = rest.slice_from(name_end).trim_left();
+
+    let mut generics = ~"";
+    if rest.starts_with("<") {
+      let end = match closing(rest, '<', '>') {
+        Some(end) => end,
+        None      => { return None; }
+      };
+      generics = rest.slice_to(end + 1).to_owned();
+      rest = rest.slice_from(end + 1).trim_left();
+    }
+
+    if name.is_empty() || !rest.starts_with("(") {
+      return None;
+    }
+    let end = match closing(rest, '(', ')') {
+      Some(end) => end,
+      None      => { return None; }
+    };
+
+    let mut params = ~[];
+    for param in split(rest.slice(1, end), ',').move_iter() {
+      if param.trim().is_empty() {
+        continue;
+      }
+      match split_type(param) {
+        Some(param) => params.push(param),
+        None        => { return None; }
+      }
+    }
+
+    let rest = rest.slice_from(end + 1).trim();
+    let output = if rest.is_empty() {
+      None
+    } else if rest.starts_with("->") {
+      Some(rest.slice_from(2).trim().to_owned())
+    } else {
+      return None;
+    };
+
+    Some(Signature{prefix: prefix, name: name, generics: generics, params: params, output: output})
+  }
+
+  /**
+    `with_writer` returns the `Signature` with a `writer` parameter of a
+    generic `WRITER_TYPE` type injected first
+    */
+  pub fn with_writer(&self) -> Signature {
+    let bound = format!("{}: Writer", WRITER_TYPE);
+    let generics = if self.generics.is_empty() {
+      format!("<{}>", bound)
+    } else {
+      // Lifetimes must come first, so the writer goes last
+      format!("{}, {}>", self.generics.slice_to(self.generics.len() - 1), bound)
+    };
+
+    let mut params = ~[Param{pattern: ~"writer", ty: format!("&mut {}", WRITER_TYPE)}];
+    params.push_all(self.params.as_slice());
+
+    Signature{prefix: self.prefix.clone(), name: self.name.clone(), generics: generics, params: params, output: self.output.clone()}
+  }
+
+  /**
+    `returns_io_result` tells whether the function returns what ers makes
+    template functions return: no return type, or an `IoResult<()>`
+    */
+  pub fn returns_io_result(&self) -> bool {
+    match self.output {
+      Some(ref output) => {
+        let output : ~str = output.chars().filter(|c| !c.is_whitespace()).collect();
+        output == ~"IoResult<()>" || output.ends_with("::IoResult<()>")
+      },
+      None => true
+    }
+  }
+
+  /**
+    `arguments` lists the names of the parameters after the first `skip`
+    ones, separated by commas, to forward them to the function
+    */
+  pub fn arguments(&self, skip: uint) -> Option<~str> {
+    let mut names = ~[];
+
+    for param in self.params.slice_from(skip).iter() {
+      match param.name() {
+        Some(name) => names.push(name),
+        None       => { return None; }
+      }
+    }
+    Some(names.connect(", "))
+  }
+
+  /**
+    `parameters` writes the parameters after the first `skip` ones back as
+    Rust code
+    */
+  pub fn parameters(&self, skip: uint) -> ~str {
+    let params : ~[~str] = self.params.slice_from(skip).iter().
+      map(|param| format!("{}: {}", param.pattern, param.ty)).collect();
+
+    params.connect(", ")
+  }
+}
+
+/**
+Structure
+*/
+#[deriving(Eq, Clone)]
+pub struct Structure {
+  /// Everything up to the struct's name, e.g. `pub struct`
+  prefix: ~str,
+
+  /// Name of the struct
+  name: ~str,
+
+  /// Generic parameters including their brackets, e.g. `<'a>`, or an empty
+  /// string
+  generics: ~str,
+
+  /// Fields of the struct, in order
+  fields: ~[Param]
+}
+
+impl Structure {
+  /**
+    `parse` splits the content of a `Declaration` block, like
+    `struct UserPage { user: User, flash: Option<~str> }`, into a `Structure`
+    */
+  pub fn parse(declaration: &str) -> Option<Structure> {
+    let declaration = declaration.trim();
+
+    let start = if declaration.starts_with("struct ") {
+      0
+    } else if declaration.starts_with("pub struct ") {
+      4
+    } else {
+      return None;
+    };
+    let prefix = declaration.slice_to(start + 6).to_owned();
+    let rest   = declaration.slice_from(start + 6).trim_left();
+
+    let name_end = match rest.find(|c: char| !(c.is_alphanumeric() || c == '_')) {
+      Some(index) => index,
+      None        => { return None; }
+    };
+    let name = rest.slice_to(name_end).to_owned();
+    let mut rest = rest.slice_from(name_end).trim_left();
+
+    let mut generics = ~"";
+    if rest.starts_with("<") {
+      let end = match closing(rest, '<', '>') {
+        Some(end) => end,
+        None      => { return None; }
+      };
+      generics = rest.slice_to(end + 1).to_owned();
+      rest = rest.slice_from(end + 1).trim_left();
+    }
+
+    if name.is_empty() || !rest.starts_with("{") || !rest.ends_with("}") {
+      return None;
+    }
+
+    let mut fields = ~[];
+    for field in split(rest.slice(1, rest.len() - 1), ',').move_iter() {
+      if field.trim().is_empty() {
+        continue;
+      }
+      match split_type(field) {
+        Some(mut field) => {
+          if field.pattern.starts_with("pub ") {
+            field.pattern = field.pattern.slice_from(4).trim().to_owned();
+          }
+          fields.push(field);
+        },
+        None => { return None; }
+      }
+    }
+
+    if fields.is_empty() {
+      return None;
+    }
+    Some(Structure{prefix: prefix, name: name, generics: generics, fields: fields})
+  }
+
+  /**
+    `type_name` is the name of the struct followed by the names of its
+    generic parameters, as used in `impl` blocks, e.g. `UserPage<'a>`
+    */
+  pub fn type_name(&self) -> ~str {
+    if self.generics.is_empty() {
+      return self.name.clone();
+    }
+
+    let names : ~[~str] = split(self.generics.slice(1, self.generics.len() - 1), ',').iter().
+      map(|param| param.split(':').next().unwrap().trim().to_owned()).collect();
+    format!("{}<{}>", self.name, names.connect(", "))
+  }
+
+  /**
+    `destructuring` is the pattern binding a reference to every field of
+    the struct, e.g. `UserPage { user: ref user }`
+    */
+  pub fn destructuring(&self) -> ~str {
+    let fields : ~[~str] = self.fields.iter().
+      map(|field| format!("{}: ref {}", field.pattern, field.pattern)).collect();
+
+    let mut pattern = self.name.clone();
+    pattern.push_str(" { ");
+    pattern.push_str(fields.connect(", "));
+    pattern.push_str(" }");
+    pattern
+  }
+}
+
+impl fmt::Show for Signature {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    try!(write!(f.buf, "{} {}{}({})", self.prefix, self.name, self.generics, self.parameters(0)));
+    match self.output {
+      Some(ref output) => write!(f.buf, " -> {}", output),
+      None             => Ok(())
+    }
+  }
+}
+
+/**
+  `closing` returns the index of the `close` character matching the `open`
+  character `input` starts with
+  */
+fn closing(input: &str, open: char, close: char) -> Option<uint> {
+  let mut depth = 0;
+  let mut previous = ' ';
+
+  for (index, c) in input.char_indices() {
+    if c == open {
+      depth += 1;
+    // `->` in function types does not close anything
+    } else if c == close && !(close == '>' && previous == '-') {
+      depth -= 1;
+      if depth == 0 {
+        return Some(index);
+      }
+    }
+    previous = c;
+  }
+  None
+}
+
+/**
+  `split` cuts `input` at each `separator` that is not nested in brackets or
+  in the `|...|` arguments of a closure type
+  */
+fn split(input: &str, separator: char) -> ~[~str] {
+  let mut parts    = ~[];
+  let mut current  = ~"";
+  let mut depth    = 0;
+  let mut closure  = false;
+  let mut previous = ' ';
+
+  for c in input.chars() {
+    match c {
+      '(' | '[' | '<'                               => { depth += 1; },
+      '>' if previous == '-'                        => {},
+      ')' | ']' | '>'                               => { depth -= 1; },
+      '|' if depth == 0                             => { closure = !closure; },
+      _ if c == separator && depth == 0 && !closure => {
+        parts.push(current.clone());
+        current.truncate(0);
+        previous = c;
+        continue;
+      },
+      _ => {}
+    }
+    current.push_char(c);
+    previous = c;
+  }
+  parts.push(current);
+  parts
+}
+
+/**
+  `split_type` splits a parameter like `user: &User` at its first lone colon
+  */
+fn split_type(param: &str) -> Option<Param> {
+  let chars : ~[char] = param.chars().collect();
+
+  for (index, &c) in chars.iter().enumerate() {
+    let double = (index > 0 && chars[index - 1] == ':') ||
+                 (index + 1 < chars.len() && chars[index + 1] == ':');
+    if c == ':' && !double {
+      let pattern : ~str = chars.slice_to(index).iter().map(|&c| c).collect();
+      let ty      : ~str = chars.slice_from(index + 1).iter().map(|&c| c).collect();
+      return Some(Param{pattern: pattern.trim().to_owned(), ty: ty.trim().to_owned()});
+    }
+  }
+  None
+}
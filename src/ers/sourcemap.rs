@@ -0,0 +1,280 @@
+// ers - ERb-like template engine
+// Copyright (C) 2014 Franck Verrot <franck@verrot.fr>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::io::{IoResult, Writer};
+use serialize::json;
+use serialize::json::Json;
+use blocks::Block;
+
+/// First word of a source map file
+pub static MAGIC: &'static str = "ers-sourcemap";
+
+/**
+LineCounter
+*/
+pub struct LineCounter<'a> {
+  /// Writer the generated code goes to
+  priv writer: &'a mut Writer,
+
+  /// Number of newlines written so far
+  priv newlines: uint
+}
+
+impl<'a> LineCounter<'a> {
+  /**
+    Creates a `LineCounter` forwarding everything to `writer`
+    */
+  pub fn new(writer: &'a mut Writer) -> LineCounter<'a> {
+    LineCounter{writer: writer, newlines: 0}
+  }
+
+  /**
+    `line` is the line the next character will be written on, starting at 1
+    */
+  pub fn line(&self) -> uint {
+    self.newlines + 1
+  }
+}
+
+impl<'a> Writer for LineCounter<'a> {
+  fn write(&mut self, buf: &[u8]) -> IoResult<()> {
+    self.newlines += buf.iter().count(|&b| b == '\n' as u8);
+    self.writer.write(buf)
+  }
+
+  fn flush(&mut self) -> IoResult<()> {
+    self.writer.flush()
+  }
+}
+
+/**
+Mapping
+*/
+#[deriving(Eq, Clone)]
+pub struct Mapping {
+  /// Line of the generated code where the block starts
+  generated: uint,
+
+  /// Last line of the generated code of the block
+  end: uint,
+
+  /// Template the block was read from
+  path: ~str,
+
+  /// Line of the block in the template
+  line_no: int,
+
+  /// Column of the block in the template
+  column: int
+}
+
+/**
+SourceMap
+*/
+#[deriving(Eq, Clone)]
+pub struct SourceMap {
+  /// Path of the generated Rust file
+  generated: ~str,
+
+  /// Mappings, ordered by generated line
+  mappings: ~[Mapping]
+}
+
+impl SourceMap {
+  /**
+    Creates an empty `SourceMap` for the generated file at `generated`
+    */
+  pub fn new(generated: ~str) -> SourceMap {
+    SourceMap{generated: generated, mappings: ~[]}
+  }
+
+  /**
+    `add` records that the code of `block` spans the `generated` to `end`
+    lines
+    */
+  pub fn add(&mut self, generated: uint, end: uint, block: &Block) {
+    self.mappings.push(Mapping{generated: generated, end: end, path: block.path.clone(),
+                               line_no: block.pos.start.line_no, column: block.pos.start.column});
+  }
+
+  /**
+    `lookup` returns the template, line and column the `generated` line
+    comes from. Lines after the start of a block are counted from it, and
+    lines ers wrote on its own, outside of every block, come from nowhere
+    */
+  pub fn lookup(&self, generated: uint) -> Option<(~str, int, int)> {
+    let mapping = match self.mappings.iter().find(|m| m.generated <= generated && generated <= m.end) {
+      Some(mapping) => mapping,
+      None          => { return None; }
+    };
+    let offset = (generated - mapping.generated) as int;
+
+    Some((mapping.path.clone(), mapping.line_no + offset, if offset == 0 { mapping.column } else { 1 }))
+  }
+
+  /**
+    `write` writes the `SourceMap`: a `MAGIC` line naming the generated
+    file, then one `generated end line column path` line per mapping
+    */
+  pub fn write(&self, writer: &mut Writer) -> IoResult<()> {
+    try!(writer.write_line(format!("{} {}", MAGIC, self.generated)));
+    for m in self.mappings.iter() {
+      try!(writer.write_line(format!("{} {} {} {} {}", m.generated, m.end, m.line_no, m.column, m.path)));
+    }
+    Ok(())
+  }
+
+  /**
+    `parse` reads a `SourceMap` written by `write`
+    */
+  pub fn parse(input: &str) -> Result<SourceMap, ~str> {
+    let mut lines = input.lines();
+
+    let generated = match lines.next() {
+      Some(line) if line.starts_with(MAGIC) => line.slice_from(MAGIC.len()).trim().to_owned(),
+      _ => { return Err(format!("missing `{}` header", MAGIC)); }
+    };
+    let mut map = SourceMap::new(generated);
+
+    for line in lines {
+      if line.trim().is_empty() {
+        continue;
+      }
+
+      let fields : ~[&str] = line.splitn(' ', 4).collect();
+      if fields.len() != 5 {
+        return Err(format!("invalid mapping `{}`", line));
+      }
+      match (from_str::<uint>(fields[0]), from_str::<uint>(fields[1]), from_str::<int>(fields[2]), from_str::<int>(fields[3])) {
+        (Some(generated), Some(end), Some(line_no), Some(column)) => {
+          map.mappings.push(Mapping{generated: generated, end: end, path: fields[4].to_owned(), line_no: line_no, column: column});
+        },
+        _ => { return Err(format!("invalid mapping `{}`", line)); }
+      }
+    }
+    Ok(map)
+  }
+
+  /**
+    `explain` rewrites a line of compiler output so the spans it mentions in
+    the generated file point into the templates instead. Both
+    `file:line:col: line:col message` lines and JSON diagnostics are
+    understood; anything else is returned as is
+    */
+  pub fn explain(&self, line: &str) -> ~str {
+    if line.trim_left().starts_with("{") {
+      match json::from_str(line) {
+        Ok(diagnostic) => { return self.explain_json(diagnostic).to_str(); },
+        Err(_)         => { return line.to_owned(); }
+      }
+    }
+    self.explain_text(line)
+  }
+
+  /**
+    `explain_text` rewrites `file:line:col: line:col message` lines
+    */
+  fn explain_text(&self, line: &str) -> ~str {
+    let prefix = format!("{}:", self.generated);
+    if !line.starts_with(prefix) {
+      return line.to_owned();
+    }
+
+    let mut words   = line.slice_from(prefix.len()).splitn(' ', 1);
+    let location    = words.next().unwrap_or("");
+    let mut message = words.next().unwrap_or("");
+
+    // The end of the span in the generated file means nothing in templates
+    match message.find(' ') {
+      Some(index) if is_location(message.slice_to(index)) => { message = message.slice_from(index + 1); },
+      _ => {}
+    }
+
+    match location.split(':').next().and_then(|l| from_str::<uint>(l)).and_then(|l| self.lookup(l)) {
+      Some((path, line_no, column)) => format!("{}:{}:{}: {}", path, line_no, column, message),
+      None                          => line.to_owned()
+    }
+  }
+
+  /**
+    `explain_json` rewrites the spans of a JSON diagnostic and of its
+    children
+    */
+  fn explain_json(&self, diagnostic: Json) -> Json {
+    match diagnostic {
+      json::Object(mut object) => {
+        for key in [~"spans", ~"children"].iter() {
+          let rewritten = match object.find(key) {
+            Some(&json::List(ref items)) => {
+              let items : ~[Json] = items.iter().map(|item| {
+                if *key == ~"spans" { self.explain_span(item.clone()) } else { self.explain_json(item.clone()) }
+              }).collect();
+              Some(json::List(items))
+            },
+            _ => None
+          };
+          match rewritten {
+            Some(list) => { object.insert(key.clone(), list); },
+            None       => {}
+          }
+        }
+        json::Object(object)
+      },
+      other => other
+    }
+  }
+
+  /**
+    `explain_span` rewrites a JSON span pointing into the generated file
+    */
+  fn explain_span(&self, span: Json) -> Json {
+    match span {
+      json::Object(mut object) => {
+        let in_generated = match object.find(&~"file_name") {
+          Some(&json::String(ref name)) => *name == self.generated,
+          _                             => false
+        };
+        if !in_generated {
+          return json::Object(object);
+        }
+
+        for &(line_key, column_key) in [("line_start", "column_start"), ("line_end", "column_end")].iter() {
+          let line = match object.find(&line_key.to_owned()) {
+            Some(&json::Number(line)) => line as uint,
+            _                         => continue
+          };
+          match self.lookup(line) {
+            Some((path, line_no, column)) => {
+              object.insert(~"file_name", json::String(path));
+              object.insert(line_key.to_owned(), json::Number(line_no as f64));
+              object.insert(column_key.to_owned(), json::Number(column as f64));
+            },
+            None => {}
+          }
+        }
+        json::Object(object)
+      },
+      other => other
+    }
+  }
+}
+
+/**
+  `is_location` tells whether `word` looks like `line:column`
+  */
+fn is_location(word: &str) -> bool {
+  !word.is_empty() && word.chars().all(|c| c.is_digit() || c == ':')
+}
@@ -0,0 +1,161 @@
+// ers - ERb-like template engine
+// Copyright (C) 2014 Franck Verrot <franck@verrot.fr>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+
+/// Prefix of the optional first line of a template that changes its `Syntax`,
+/// e.g. `#ers-syntax open=<< close=>>`
+pub static DIRECTIVE: &'static str = "#ers-syntax";
+
+/**
+Syntax
+*/
+#[deriving(Eq, Clone)]
+pub struct Syntax {
+  /// Opens every tag, `<%` by default
+  open: ~str,
+
+  /// Closes every tag, `%>` by default
+  close: ~str,
+
+  /// Follows `open` in `Declaration` tags, `!` by default
+  declaration: char,
+
+  /// Follows `open` and precedes `close` in `Header` tags, `%` by default
+  header: char,
+
+  /// Follows `open` in `Print` tags, `=` by default. Doubled in `Raw` tags
+  print: char,
+
+  /// Follows `open` in `Debug` tags, `?` by default
+  debug: char,
+
+  /// Follows `open` in `Comment` tags, `#` by default
+  comment: char,
+
+  /// Follows `open` in `Directive` tags, `@` by default
+  directive: char,
+
+  /// Follows `open` in `Partial` tags, `>` by default
+  partial: char,
+
+  /// Follows `open` or precedes `close` to trim whitespace, `-` by default
+  trim: char
+}
+
+impl Syntax {
+  /**
+    Creates the default, ERb-like, `Syntax`
+    */
+  pub fn new() -> Syntax {
+    Syntax {
+      open: ~"<%",
+      close: ~"%>",
+      declaration: '!',
+      header: '%',
+      print: '=',
+      debug: '?',
+      comment: '#',
+      directive: '@',
+      partial: '>',
+      trim: '-'
+    }
+  }
+
+  /**
+    `from_directive` builds a `Syntax` out of the settings following
+    `DIRECTIVE`, a list of space-separated `key=value` pairs overriding the
+    default `Syntax`
+    */
+  pub fn from_directive(settings: &str) -> Result<Syntax, ~str> {
+    let mut syntax = Syntax::new();
+
+    for setting in settings.words() {
+      let (key, value) = match setting.find('=') {
+        Some(index) => (setting.slice_to(index), setting.slice_from(index + 1)),
+        None        => { return Err(format!("expected `key=value`, found `{}`", setting)); }
+      };
+
+      if value.is_empty() {
+        return Err(format!("`{}` cannot be empty", key));
+      }
+
+      match key {
+        "open"  => { syntax.open  = value.to_owned(); },
+        "close" => { syntax.close = value.to_owned(); },
+        _ => {
+          if value.char_len() != 1 {
+            return Err(format!("`{}` must be a single character", key));
+          }
+
+          let sigil = value.char_at(0);
+          match key {
+            "declaration" => { syntax.declaration = sigil; },
+            "header"      => { syntax.header      = sigil; },
+            "print"       => { syntax.print       = sigil; },
+            "debug"       => { syntax.debug       = sigil; },
+            "comment"     => { syntax.comment     = sigil; },
+            "directive"   => { syntax.directive   = sigil; },
+            "partial"     => { syntax.partial     = sigil; },
+            "trim"        => { syntax.trim        = sigil; },
+            _             => { return Err(format!("unknown setting `{}`", key)); }
+          }
+        }
+      }
+    }
+    try!(syntax.validate());
+    Ok(syntax)
+  }
+
+  /**
+    `validate` checks that tags written with the `Syntax` can be told apart:
+    delimiters must not be empty or equal, sigils must all differ, and the
+    trim sigil must not start `close`
+    */
+  pub fn validate(&self) -> Result<(), ~str> {
+    if self.open.is_empty() || self.close.is_empty() {
+      return Err(~"`open` and `close` cannot be empty");
+    }
+    if self.open == self.close {
+      return Err(~"`open` and `close` must differ");
+    }
+    if self.trim == self.close.char_at(0) {
+      return Err(~"`trim` cannot start `close`");
+    }
+
+    let sigils = [("declaration", self.declaration), ("header", self.header), ("print", self.print),
+                  ("debug", self.debug), ("comment", self.comment), ("directive", self.directive),
+                  ("partial", self.partial), ("trim", self.trim)];
+
+    for (i, &(name, sigil)) in sigils.iter().enumerate() {
+      if sigil.is_whitespace() {
+        return Err(format!("`{}` cannot be whitespace", name));
+      }
+      for &(other, other_sigil) in sigils.slice_from(i + 1).iter() {
+        if sigil == other_sigil {
+          return Err(format!("`{}` and `{}` are both `{}`", name, other, sigil));
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /**
+    `header_close` is the sequence closing `Header` tags, e.g. `%%>`
+    */
+  pub fn header_close(&self) -> ~str {
+    format!("{}{}", self.header, self.close)
+  }
+}
@@ -14,9 +14,53 @@
 // You should have received a copy of the GNU General Public License
 // along with this program.  If not, see <http://www.gnu.org/licenses/>.
 
-use blocks::Block;
+use std::fmt;
+use std::mem;
+use std::ascii::StrAsciiExt;
+use std::io::{IoError, IoResult};
+use blocks::{Block, Pos};
 use blocks::Header;
 use blocks::Declaration;
+use blocks::Comment;
+use blocks::Text;
+use blocks::{Yield, Directive, Partial};
+use blocks::split_call;
+use blocks::RETURN_TYPE;
+use signature::{Signature, Structure, WRITER_TYPE};
+use sourcemap::{SourceMap, LineCounter};
+use parser::ParseError;
+use tree;
+use tree::Node;
+
+/// Return type of the `_to_string` companions
+static STRING_RESULT: &'static str = "::std::io::IoResult<~str>";
+
+/**
+WriteOptions
+*/
+#[deriving(Eq, Clone)]
+pub struct WriteOptions {
+  /// Emit a `<name>_to_string` companion for every function, rendering the
+  /// template into a `~str`
+  to_string: bool,
+
+  /// Make functions generic over the type of their writer. Declarations
+  /// then leave the writer out, and ers injects it
+  generic_writer: bool,
+
+  /// Write static text as bytes, skipping the UTF-8 checks of `write_str`
+  byte_strings: bool
+}
+
+impl WriteOptions {
+  /**
+    Creates the default `WriteOptions`, emitting nothing but the template
+    functions
+    */
+  pub fn new() -> WriteOptions {
+    WriteOptions{to_string: false, generic_writer: false, byte_strings: false}
+  }
+}
 
 /**
 Template
@@ -32,49 +76,379 @@ pub struct Template {
 
 impl Template {
   /**
-    `write_formatted` will write the `Template` content to the `writer`
+    `write_formatted` will write the `Template` content to the `writer`.
+    Headers are shared by every function, and each declaration starts a
+    function that runs until the next declaration
     */
-  #[allow(unused_must_use)]
   pub fn write_formatted(&self, writer: &mut Writer) -> Result<int, TemplateWriteError> {
-    let mut w = writer;
+    self.write_formatted_with(writer, &WriteOptions::new())
+  }
+
+  /**
+    `write_formatted_with` will write the `Template` content to the
+    `writer`, along with the companions `options` ask for
+    */
+  pub fn write_formatted_with(&self, writer: &mut Writer, options: &WriteOptions) -> Result<int, TemplateWriteError> {
+    self.write_formatted_mapped(writer, options, &mut SourceMap::new(~""))
+  }
+
+  /**
+    `write_formatted_mapped` will write the `Template` content to the
+    `writer` like `write_formatted_with`, recording in `map` the generated
+    lines each block spans. Consecutive text is written at once, see
+    `coalesced`
+    */
+  pub fn write_formatted_mapped(&self, writer: &mut Writer, options: &WriteOptions, map: &mut SourceMap) -> Result<int, TemplateWriteError> {
+    try!(self.validate());
+
+    for block in self.blocks.iter().filter(|block| block.class == Declaration) {
+      try!(signature_for(&**block, options));
+    }
+
+    let coalesced = self.coalesced();
+    let mut w = LineCounter::new(writer);
     let mut blocks = 0;
 
     // Write headers
-    let mut headers = self.blocks.iter().
-      filter(|&x|
-             match x.class {
-               Header => { return true },
-               _      => { return false }
-             }
-            );
+    let headers : ~[&Block] = coalesced.iter().
+      filter(|&x| x.class == Header).
+      map(|x| &**x).
+      collect();
 
-    // Write Declaration
-    let mut declarations = self.blocks.iter().
+    // Write functions, comments aside
+    let mut bodies = coalesced.iter().
       filter(|&x|
              match x.class {
-               Declaration => { return true },
-               _           => { return false }
+               Header | Comment => { return false },
+               _                => { return true }
              }
             );
 
-    // Write Declaration
-    let mut allOtherBlocks = self.blocks.iter().
-      filter(|&x|
-             match x.class {
-               Header | Declaration => { return false },
-               _           => { return true }
-             }
-            );
+    // Inner attributes must come before any item
+    for &block in headers.iter().filter(|&&x| is_inner_attribute(x)) {
+      blocks+=1;
+      try!(Template::write_header(&mut w, block, map));
+    }
+
+    // Generated code relies on ers' runtime helpers
+    if !headers.iter().any(|&x| imports_ers(x)) {
+      try!(written(w.write_line("extern crate ers;")));
+    }
+
+    for &block in headers.iter().filter(|&&x| !is_inner_attribute(x)) {
+      blocks+=1;
+      try!(Template::write_header(&mut w, block, map));
+    }
 
-    for block in headers        { blocks+=1; block.write(&mut w); }
-    for block in declarations   { blocks+=1; block.write(&mut w); }
-    for block in allOtherBlocks { blocks+=1; block.write(&mut w); }
+    let mut declaration : Option<&~Block> = None;
+    let mut static_len = 0u;
+    for block in bodies {
+      match block.class {
+        Declaration => {
+          match declaration {
+            Some(current) => try!(Template::close_function(&mut w, &**current, static_len, options)),
+            None          => {}
+          }
+          declaration = Some(block);
+          static_len  = 0;
+        },
+        // Blank text before the first declaration belongs to no function
+        _ if declaration.is_none() => { continue; },
+        Text => { static_len += block.content.len(); },
+        _ => {}
+      }
+      blocks+=1;
 
-    w.write_line("writer.flush();");
-    w.write_line("}\n");
+      let start = w.line() + 2;
+      if block.class == Declaration && is_struct(&**block) {
+        let structure = try!(structure_for(&**block));
+        try!(written(Template::write_struct_declaration(&mut w, &**block)));
+        // Only the struct comes from the template, not its generated `render`
+        map.add(start, w.line() - 1, &**block);
+        try!(written(Template::open_struct_render(&mut w, &structure, options)));
+        continue;
+      } else if block.class == Text && options.byte_strings {
+        try!(written(Template::write_text_bytes(&mut w, &**block)));
+      } else if block.class == Declaration && options.generic_writer {
+        let signature = try!(signature_for(&**block, options)).unwrap();
+        try!(written(Template::write_generic_declaration(&mut w, &**block, &signature)));
+      } else {
+        try!(written(block.write(&mut w)));
+      }
+
+      // Yields and directives write nothing
+      if block.class != Yield && block.class != Directive {
+        map.add(start, w.line() - 1, &**block);
+      }
+    }
+
+    try!(Template::close_function(&mut w, &**declaration.unwrap(), static_len, options));
     Ok(blocks)
   }
 
+  /**
+    `write_header` writes the `Header` `block`, recording in `map` the
+    lines it spans
+    */
+  fn write_header(w: &mut LineCounter, block: &Block, map: &mut SourceMap) -> Result<(), TemplateWriteError> {
+    // Every block starts with a blank line and its `//line` comment
+    let start = w.line() + 2;
+    try!(written(block.write(w)));
+    map.add(start, w.line() - 1, block);
+    Ok(())
+  }
+
+  /**
+    `close_function` ends the function opened by the `declaration` block,
+    whose static text is `static_len` bytes long, then writes its companions
+    */
+  fn close_function(writer: &mut Writer, declaration: &Block, static_len: uint, options: &WriteOptions) -> Result<(), TemplateWriteError> {
+    try!(written(writer.write_line("try!(writer.flush());")));
+    try!(written(writer.write_line("Ok(())")));
+    try!(written(writer.write_line("}\n")));
+
+    let hint = hint_prefix(declaration);
+    if is_struct(declaration) {
+      if options.to_string {
+        try!(written(Template::write_struct_to_string(writer, hint.get_ref().as_slice())));
+      }
+      try!(written(writer.write_line("}\n")));
+    }
+
+    match hint {
+      Some(ref prefix) => try!(written(Template::write_size_hint(writer, prefix.as_slice(), static_len, options))),
+      None             => {}
+    }
+
+    if options.to_string && !is_struct(declaration) {
+      let signature = try!(signature_for(declaration, options)).unwrap();
+      try!(written(Template::write_to_string(writer, &signature, writer_params(options), hint.get_ref().as_slice())));
+    }
+    Ok(())
+  }
+
+  /**
+    `write_size_hint` writes `<PREFIX>_SIZE_HINT`, the length of the static
+    text of a function, and the size learned from previous renders into a
+    string
+    */
+  fn write_size_hint(writer: &mut Writer, prefix: &str, static_len: uint, options: &WriteOptions) -> IoResult<()> {
+    try!(writer.write_line(format!("pub static {}_SIZE_HINT: uint = {};", prefix, static_len)));
+    if options.to_string {
+      try!(writer.write_line(format!("static mut {}_LEARNED_SIZE: ::std::sync::atomics::AtomicUint = ::std::sync::atomics::INIT_ATOMIC_UINT;", prefix)));
+    }
+    writer.write_line("")
+  }
+
+  /**
+    `write_render_buffer` opens an in-memory buffer sized from the hints of
+    `prefix`, renders into it with `render`, and returns its content or the
+    error the rendering failed with
+    */
+  fn write_render_buffer(writer: &mut Writer, prefix: &str, render: &str) -> IoResult<()> {
+    // `<PREFIX>_LEARNED_SIZE` is mutable to be stored to, but only ever
+    // accessed atomically
+    try!(writer.write_str("let mut writer = ::std::io::MemWriter::with_capacity(unsafe { "));
+    try!(writer.write_str(format!("ers::runtime::size_hint({0}_SIZE_HINT, &{0}_LEARNED_SIZE)", prefix)));
+    try!(writer.write_line(" });"));
+    try!(writer.write_line(format!("try!({});", render)));
+    try!(writer.write_str("unsafe { "));
+    try!(writer.write_str(format!("ers::runtime::learn_size(&mut {}_LEARNED_SIZE, writer.get_ref().len());", prefix)));
+    try!(writer.write_line(" }"));
+    // A template writing raw bytes can leave invalid UTF-8 behind
+    try!(writer.write_line("match ::std::str::from_utf8_owned(writer.unwrap()) {"));
+    try!(writer.write_line("Some(output) => Ok(output),"));
+    try!(writer.write_line("None => Err(::std::io::IoError{kind: ::std::io::InvalidInput, desc: \"template rendered invalid UTF-8\", detail: None})"));
+    writer.write_line("}")
+  }
+
+  /**
+    `write_struct_declaration` writes the struct declared by `block` as is
+    */
+  fn write_struct_declaration(writer: &mut Writer, block: &Block) -> IoResult<()> {
+    try!(block.pos.write(writer));
+    writer.write_line(block.content.trim())
+  }
+
+  /**
+    `open_struct_render` opens the `render` method of `structure`, where
+    every field is bound by reference
+    */
+  fn open_struct_render(writer: &mut Writer, structure: &Structure, options: &WriteOptions) -> IoResult<()> {
+    try!(writer.write_str(format!("impl{} {}", structure.generics, structure.type_name())));
+    try!(writer.write_line(" {"));
+    try!(writer.write_line("#[allow(unused_variable)]"));
+    if options.generic_writer {
+      try!(writer.write_str(format!("pub fn render<{0}: Writer>(&self, writer: &mut {0}) -> {1}", WRITER_TYPE, RETURN_TYPE)));
+    } else {
+      try!(writer.write_str(format!("pub fn render(&self, writer: &mut Writer) -> {}", RETURN_TYPE)));
+    }
+    try!(writer.write_line(" {"));
+    writer.write_line(format!("let {} = *self;", structure.destructuring()))
+  }
+
+  /**
+    `write_struct_to_string` writes the `to_string` method of a struct,
+    returning what `render` rendered
+    */
+  fn write_struct_to_string(writer: &mut Writer, prefix: &str) -> IoResult<()> {
+    try!(writer.write_str(format!("pub fn to_string(&self) -> {}", STRING_RESULT)));
+    try!(writer.write_line(" {"));
+    try!(Template::write_render_buffer(writer, prefix, "self.render(&mut writer)"));
+    writer.write_line("}")
+  }
+
+  /**
+    `write_text_bytes` writes the `Text` `block` as a byte string literal
+    */
+  fn write_text_bytes(writer: &mut Writer, block: &Block) -> IoResult<()> {
+    try!(block.pos.write(writer));
+    writer.write_str(format!("try!(writer.write(bytes!(\"{:s}\")));\n", block.content.escape_default()))
+  }
+
+  /**
+    `write_generic_declaration` opens the function declared by `block`,
+    generic over the type of the writer it injects
+    */
+  fn write_generic_declaration(writer: &mut Writer, block: &Block, signature: &Signature) -> IoResult<()> {
+    let mut signature = signature.with_writer();
+    if signature.output.is_none() {
+      signature.output = Some(RETURN_TYPE.to_owned());
+    }
+
+    try!(block.pos.write(writer));
+    try!(writer.write_str(format!("{}", signature)));
+    writer.write_str(" {\n")
+  }
+
+  /**
+    `write_to_string` writes `<name>_to_string`, which takes the arguments of
+    the function but the `skip` first ones, its writer, and returns what it
+    rendered or the error it failed with
+    */
+  fn write_to_string(writer: &mut Writer, signature: &Signature, skip: uint, prefix: &str) -> IoResult<()> {
+    let arguments = signature.arguments(skip).unwrap();
+
+    try!(writer.write_str(format!("{} {}_to_string{}({}) -> {}",
+                                  signature.prefix, signature.name, signature.generics, signature.parameters(skip), STRING_RESULT)));
+    try!(writer.write_line(" {"));
+
+    let render = format!("{}(&mut writer{}{})",
+                         signature.name, if arguments.is_empty() { "" } else { ", " }, arguments);
+    try!(Template::write_render_buffer(writer, prefix, render.as_slice()));
+    writer.write_line("}\n")
+  }
+
+  /**
+    `validate` checks that the `Template` can be turned into Rust functions:
+    it needs at least one declaration, the first one must be preceded by
+    nothing but headers, comments and blank text, functions must return
+    `IoResult<()>`, no two declarations may share the prefix of their size
+    hints, and partials must hold calls
+    */
+  pub fn validate(&self) -> Result<(), TemplateWriteError> {
+    let mut declared    = false;
+    let mut has_content = false;
+    let mut prefixes : ~[~str] = ~[];
+
+    for block in self.blocks.iter() {
+      match block.class {
+        Declaration => {
+          if !declared && has_content {
+            return Err(DeclarationAfterContent(block.path.clone(), block.pos));
+          }
+          match Signature::parse(block.content.as_slice()) {
+            Some(ref signature) if !signature.returns_io_result() => {
+              return Err(UnsupportedReturnType(block.path.clone(), block.pos));
+            },
+            None if !is_struct(&**block) => {
+              return Err(UnsupportedDeclaration(block.path.clone(), block.pos));
+            },
+            _ => {}
+          }
+          match hint_prefix(&**block) {
+            Some(prefix) => {
+              if prefixes.contains(&prefix) {
+                return Err(SizeHintClash(block.path.clone(), block.pos));
+              }
+              prefixes.push(prefix);
+            },
+            None => {}
+          }
+          declared = true;
+        },
+        Header | Comment => {},
+        Text if block.content.trim().is_empty() => {},
+        Partial if split_call(block.content.as_slice()).is_none() => {
+          return Err(UnsupportedPartial(block.path.clone(), block.pos));
+        },
+        _ => { has_content = true; }
+      }
+    }
+
+    if !declared {
+      return Err(DeclarationNotFound);
+    }
+    Ok(())
+  }
+
+  /**
+    `tree` nests the blocks of the `Template` under the `if` and `for` tags
+    they belong to, or reports the first tag that is not balanced
+    */
+  pub fn tree(&self) -> Result<~[Node], ParseError> {
+    tree::build(self.blocks.as_slice())
+  }
+
+  /**
+    `coalesce` merges consecutive `Text` blocks read from the same template,
+    see `coalesced`
+    */
+  pub fn coalesce(&mut self) {
+    self.blocks = self.coalesced();
+  }
+
+  /**
+    `coalesced` returns the blocks of the `Template` with consecutive `Text`
+    blocks read from the same template merged, so each static run of text
+    is written at once. Comments write nothing, so text on both sides of
+    them is merged too, and they are moved after it
+    */
+  pub fn coalesced(&self) -> ~[~Block] {
+    let mut blocks   : ~[~Block] = ~[];
+    let mut text     : Option<~Block> = None;
+    let mut comments : ~[~Block] = ~[];
+
+    for block in self.blocks.iter() {
+      if text.is_some() {
+        if block.class == Comment {
+          comments.push(block.clone());
+          continue;
+        }
+
+        let last = text.get_mut_ref();
+        if block.class == Text && last.path == block.path {
+          last.content.push_str(block.content.as_slice());
+          last.pos = last.pos.merge(&block.pos);
+          continue;
+        }
+      }
+
+      blocks.push_all_move(text.take().move_iter().collect());
+      blocks.push_all_move(mem::replace(&mut comments, ~[]));
+
+      if block.class == Text {
+        text = Some(block.clone());
+      } else {
+        blocks.push(block.clone());
+      }
+    }
+
+    blocks.push_all_move(text.take().move_iter().collect());
+    blocks.push_all_move(comments);
+    blocks
+  }
+
   /**
     Creates a new template from a path and an array of blocks
     */
@@ -86,8 +460,137 @@ impl Template {
 /**
 TemplateWriteError
 */
+#[deriving(Eq, Clone)]
 pub enum TemplateWriteError {
   /// Error raised by a missing declaration
-  DeclarationNotFound
+  DeclarationNotFound,
+
+  /// Error raised by a declaration following the template's content,
+  /// holding its template and position
+  DeclarationAfterContent(~str, Pos),
+
+  /// Error raised by a declaration ers cannot generate companions for,
+  /// holding its template and position
+  UnsupportedDeclaration(~str, Pos),
+
+  /// Error raised by a function declared with a return type other than
+  /// `IoResult<()>`, holding its template and position
+  UnsupportedReturnType(~str, Pos),
+
+  /// Error raised by a declaration whose size hints would be named like
+  /// those of a previous one, e.g. `page` after `Page`, holding its template
+  /// and position
+  SizeHintClash(~str, Pos),
+
+  /// Error raised by a partial that does not hold a call like
+  /// `user_card(&user)`, holding its template and position
+  UnsupportedPartial(~str, Pos),
+
+  /// Error raised by the writer the template is written to
+  WriteFailure(IoError)
+}
+
+/**
+  `writer_params` is the number of parameters a declaration holds for the
+  writer: none when ers injects it
+  */
+fn writer_params(options: &WriteOptions) -> uint {
+  if options.generic_writer { 0 } else { 1 }
+}
+
+/**
+  `signature_for` parses `declaration` when `options` need its parts. The
+  parameters but the writer must then be plain identifiers for
+  `<name>_to_string` to forward them
+  */
+fn signature_for(declaration: &Block, options: &WriteOptions) -> Result<Option<Signature>, TemplateWriteError> {
+  if is_struct(declaration) {
+    return structure_for(declaration).map(|_| None);
+  }
+  if !options.to_string && !options.generic_writer {
+    return Ok(None);
+  }
+
+  let skip = writer_params(options);
+  match Signature::parse(declaration.content.as_slice()) {
+    Some(signature) => {
+      if signature.params.len() < skip || (options.to_string && signature.arguments(skip).is_none()) {
+        return Err(UnsupportedDeclaration(declaration.path.clone(), declaration.pos));
+      }
+      Ok(Some(signature))
+    },
+    None => Err(UnsupportedDeclaration(declaration.path.clone(), declaration.pos))
+  }
+}
+
+/**
+  `is_struct` tells whether `declaration` declares a struct rather than a
+  function
+  */
+fn is_struct(declaration: &Block) -> bool {
+  let content = declaration.content.trim();
+  content.starts_with("struct ") || content.starts_with("pub struct ")
+}
+
+/**
+  `hint_prefix` is the prefix of the size hints of the function or struct
+  declared by `declaration`, e.g. `USER_CARD` for `fn user_card(...)`
+  */
+fn hint_prefix(declaration: &Block) -> Option<~str> {
+  let content = declaration.content.as_slice();
+  let name = match (Structure::parse(content), Signature::parse(content)) {
+    (Some(structure), _) => structure.name,
+    (_, Some(signature)) => signature.name,
+    _                    => { return None; }
+  };
+  Some(name.to_ascii_upper())
+}
+
+/**
+  `is_inner_attribute` tells whether the `Header` `block` is an inner
+  attribute, like `#![feature(phase)]`, which must come before any item
+  */
+fn is_inner_attribute(block: &Block) -> bool {
+  block.content.trim().starts_with("#![")
+}
+
+/**
+  `imports_ers` tells whether the `Header` `block` already declares
+  `extern crate ers;`
+  */
+fn imports_ers(block: &Block) -> bool {
+  let words : ~[&str] = block.content.words().collect();
+  words.connect(" ").contains("extern crate ers;")
+}
+
+/**
+  `structure_for` parses the struct `declaration`
+  */
+fn structure_for(declaration: &Block) -> Result<Structure, TemplateWriteError> {
+  match Structure::parse(declaration.content.as_slice()) {
+    Some(structure) => Ok(structure),
+    None            => Err(UnsupportedDeclaration(declaration.path.clone(), declaration.pos))
+  }
+}
+
+/**
+  `written` turns the result of a write into the result of `write_formatted`
+  */
+fn written(result: IoResult<()>) -> Result<(), TemplateWriteError> {
+  result.map_err(|error| WriteFailure(error))
+}
+
+impl fmt::Show for TemplateWriteError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      DeclarationNotFound                    => write!(f.buf, "no declaration found, expected `<%! pub fn name(writer: &mut Writer, ...) %>`"),
+      DeclarationAfterContent(ref path, pos) => write!(f.buf, "{}:{}:{}: the declaration must come before the template's content", *path, pos.start.line_no, pos.start.column),
+      UnsupportedDeclaration(ref path, pos)  => write!(f.buf, "{}:{}:{}: expected a declaration like `pub fn name(arguments)`, with plain argument names, or a struct with named fields", *path, pos.start.line_no, pos.start.column),
+      UnsupportedReturnType(ref path, pos)   => write!(f.buf, "{}:{}:{}: template functions must return `IoResult<()>`", *path, pos.start.line_no, pos.start.column),
+      SizeHintClash(ref path, pos)           => write!(f.buf, "{}:{}:{}: the size hints of this declaration would clash with those of a previous one, rename it", *path, pos.start.line_no, pos.start.column),
+      UnsupportedPartial(ref path, pos)      => write!(f.buf, "{}:{}:{}: expected a call like `name(args)`", *path, pos.start.line_no, pos.start.column),
+      WriteFailure(ref error)                => write!(f.buf, "{}", error)
+    }
+  }
 }
 
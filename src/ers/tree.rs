@@ -0,0 +1,123 @@
+// ers - ERb-like template engine
+// Copyright (C) 2014 Franck Verrot <franck@verrot.fr>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use blocks::{Block, Declaration, If, Elsif, Else, For, End};
+use parser::{ParseError, UnbalancedBlock};
+
+/**
+Node
+*/
+#[deriving(Eq, Clone)]
+pub enum Node {
+  /// A block outside of control-flow tags or in one of their bodies
+  Leaf(~Block),
+
+  /// The `If` and `Elsif` or `Else` blocks of a conditional, each with its
+  /// body, followed by the `End` block closing it
+  Conditional(~[(~Block, ~[Node])], ~Block),
+
+  /// The `For` block of a loop, its body and the `End` block closing it
+  Loop(~Block, ~[Node], ~Block)
+}
+
+/**
+  `build` nests `blocks` under the control-flow tags they belong to, or
+  reports the first tag that is not balanced
+  */
+pub fn build(blocks: &[~Block]) -> Result<~[Node], ParseError> {
+  let mut index = 0;
+  let (nodes, terminator) = try!(body(blocks, &mut index, false));
+
+  match terminator {
+    Some(block) => Err(ParseError::at(&*block, UnbalancedBlock(format!("`{}` has no `if` or `for` to belong to", keyword(&*block))))),
+    None        => Ok(nodes)
+  }
+}
+
+/**
+  `keyword` is the tag a control-flow block was written with
+  */
+fn keyword(block: &Block) -> &'static str {
+  match block.class {
+    If    => "if",
+    Elsif => "elsif",
+    Else  => "else",
+    For   => "for",
+    _     => "end"
+  }
+}
+
+/**
+  `body` collects nodes from `index` on, until the end of `blocks` or the
+  `Elsif`, `Else` or `End` block ending the body, which it returns. Nested
+  bodies also end before the next declaration, which starts another function
+  */
+fn body(blocks: &[~Block], index: &mut uint, nested: bool) -> Result<(~[Node], Option<~Block>), ParseError> {
+  let mut nodes = ~[];
+
+  while *index < blocks.len() {
+    let block = blocks[*index].clone();
+
+    if nested && block.class == Declaration {
+      break;
+    }
+    *index += 1;
+
+    match block.class {
+      If => {
+        let mut branches = ~[];
+        let mut opener   = block.clone();
+        let mut has_else = false;
+
+        loop {
+          let (branch, terminator) = try!(body(blocks, index, true));
+          branches.push((opener, branch));
+
+          match terminator {
+            None => { return Err(ParseError::at(&*block, UnbalancedBlock(~"`if` is never closed by `end`"))); },
+            Some(end) => {
+              if end.class == End {
+                nodes.push(Conditional(branches, end));
+                break;
+              }
+              if has_else {
+                return Err(ParseError::at(&*end, UnbalancedBlock(format!("`{}` after `else`", keyword(&*end)))));
+              }
+              has_else = end.class == Else;
+              opener   = end;
+            }
+          }
+        }
+      },
+      For => {
+        let (inner, terminator) = try!(body(blocks, index, true));
+
+        match terminator {
+          Some(end) => {
+            if end.class != End {
+              return Err(ParseError::at(&*end, UnbalancedBlock(format!("`{}` in a `for` loop", keyword(&*end)))));
+            }
+            nodes.push(Loop(block, inner, end));
+          },
+          None => { return Err(ParseError::at(&*block, UnbalancedBlock(~"`for` is never closed by `end`"))); }
+        }
+      },
+      Elsif | Else | End => { return Ok((nodes, Some(block))); },
+      _ => nodes.push(Leaf(block))
+    }
+  }
+  Ok((nodes, None))
+}
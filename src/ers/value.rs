@@ -0,0 +1,268 @@
+// ers - ERb-like template engine
+// Copyright (C) 2014 Franck Verrot <franck@verrot.fr>
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <http://www.gnu.org/licenses/>.
+
+use std::fmt;
+use collections::TreeMap;
+use serialize::json;
+
+/**
+Value
+*/
+#[deriving(Eq, Clone)]
+pub enum Value {
+  /// Absence of a value
+  Null,
+
+  /// `true` or `false`
+  Boolean(bool),
+
+  /// Any number, integers included
+  Number(f64),
+
+  /// A string
+  String(~str),
+
+  /// An ordered list of values
+  List(~[Value]),
+
+  /// Values indexed by name
+  Object(TreeMap<~str, Value>)
+}
+
+impl Value {
+  /**
+    `is_truthy` tells whether the `Value` makes an `if` succeed: everything
+    but `Null` and `false` does
+    */
+  pub fn is_truthy(&self) -> bool {
+    match *self {
+      Null       => false,
+      Boolean(b) => b,
+      _          => true
+    }
+  }
+
+  /**
+    `type_name` names the kind of the `Value`, for error messages
+    */
+  pub fn type_name(&self) -> &'static str {
+    match *self {
+      Null       => "null",
+      Boolean(_) => "boolean",
+      Number(_)  => "number",
+      String(_)  => "string",
+      List(_)    => "list",
+      Object(_)  => "object"
+    }
+  }
+
+  /**
+    `debug` formats the `Value` like `<%? %>` does: as `Show` does, with
+    strings quoted
+    */
+  pub fn debug(&self) -> ~str {
+    match *self {
+      String(ref s) => format!("\"{}\"", s.escape_default()),
+      List(ref items) => {
+        let items : ~[~str] = items.iter().map(|item| item.debug()).collect();
+        format!("[{}]", items.connect(", "))
+      },
+      _ => format!("{}", *self)
+    }
+  }
+}
+
+impl fmt::Show for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match *self {
+      Null          => Ok(()),
+      Boolean(b)    => write!(f.buf, "{}", b),
+      Number(n) if n == n.floor() && n.abs() < 1e15 => write!(f.buf, "{}", n as i64),
+      Number(n)     => write!(f.buf, "{}", n),
+      String(ref s) => write!(f.buf, "{}", *s),
+      List(ref items) => {
+        let items : ~[~str] = items.iter().map(|item| format!("{}", *item)).collect();
+        write!(f.buf, "[{}]", items.connect(", "))
+      },
+      Object(ref fields) => {
+        let fields : ~[~str] = fields.iter().map(|(name, value)| format!("{}: {}", *name, *value)).collect();
+        try!(f.buf.write_str("{"));
+        try!(f.buf.write_str(fields.connect(", ")));
+        f.buf.write_str("}")
+      }
+    }
+  }
+}
+
+/**
+ToValue
+*/
+pub trait ToValue {
+  /**
+    `to_value` converts to a `Value` templates can be rendered against
+    */
+  fn to_value(&self) -> Value;
+}
+
+impl ToValue for Value {
+  fn to_value(&self) -> Value { self.clone() }
+}
+
+impl ToValue for bool {
+  fn to_value(&self) -> Value { Boolean(*self) }
+}
+
+impl ToValue for int {
+  fn to_value(&self) -> Value { Number(*self as f64) }
+}
+
+impl ToValue for uint {
+  fn to_value(&self) -> Value { Number(*self as f64) }
+}
+
+impl ToValue for i64 {
+  fn to_value(&self) -> Value { Number(*self as f64) }
+}
+
+impl ToValue for f64 {
+  fn to_value(&self) -> Value { Number(*self) }
+}
+
+impl<'a> ToValue for &'a str {
+  fn to_value(&self) -> Value { String(self.to_owned()) }
+}
+
+impl ToValue for ~str {
+  fn to_value(&self) -> Value { String(self.clone()) }
+}
+
+impl<T: ToValue> ToValue for Option<T> {
+  fn to_value(&self) -> Value {
+    match *self {
+      Some(ref value) => value.to_value(),
+      None            => Null
+    }
+  }
+}
+
+impl<T: ToValue> ToValue for ~[T] {
+  fn to_value(&self) -> Value {
+    List(self.iter().map(|item| item.to_value()).collect())
+  }
+}
+
+impl<T: ToValue> ToValue for TreeMap<~str, T> {
+  fn to_value(&self) -> Value {
+    let mut fields = TreeMap::new();
+    for (name, value) in self.iter() {
+      fields.insert(name.clone(), value.to_value());
+    }
+    Object(fields)
+  }
+}
+
+impl ToValue for json::Json {
+  fn to_value(&self) -> Value {
+    match *self {
+      json::Null            => Null,
+      json::Boolean(b)      => Boolean(b),
+      json::Number(n)       => Number(n),
+      json::String(ref s)   => String(s.clone()),
+      json::List(ref items) => items.to_value(),
+      json::Object(ref fields) => fields.to_value()
+    }
+  }
+}
+
+/**
+Context
+*/
+pub struct Context {
+  /// Scopes of variables, the innermost last
+  priv frames: ~[TreeMap<~str, Value>]
+}
+
+impl Context {
+  /**
+    Creates a `Context` with a single, empty scope
+    */
+  pub fn new() -> Context {
+    Context{frames: ~[TreeMap::new()]}
+  }
+
+  /**
+    Creates a `Context` whose variables are the fields of the JSON object
+    `source`
+    */
+  pub fn from_json(source: &str) -> Result<Context, ~str> {
+    let json = match json::from_str(source) {
+      Ok(json)   => json,
+      Err(error) => { return Err(format!("{}", error)); }
+    };
+
+    match json.to_value() {
+      Object(fields) => {
+        let mut context = Context::new();
+        for (name, value) in fields.move_iter() {
+          context.set(name, value);
+        }
+        Ok(context)
+      },
+      other => Err(format!("expected a JSON object, found a {}", other.type_name()))
+    }
+  }
+
+  /**
+    `push` opens a scope, whose variables hide the ones with the same names
+    until it is popped
+    */
+  pub fn push(&mut self) {
+    self.frames.push(TreeMap::new());
+  }
+
+  /**
+    `pop` closes the innermost scope, forgetting its variables. The
+    outermost scope is never closed
+    */
+  pub fn pop(&mut self) {
+    if self.frames.len() > 1 {
+      self.frames.pop();
+    }
+  }
+
+  /**
+    `set` binds `name` to `value` in the innermost scope, returning the value
+    it replaces in that scope
+    */
+  pub fn set(&mut self, name: ~str, value: Value) -> Option<Value> {
+    self.frames.mut_last().unwrap().swap(name, value)
+  }
+
+  /**
+    `get` returns the value bound to `name` by the innermost scope binding
+    it
+    */
+  pub fn get<'a>(&'a self, name: &str) -> Option<&'a Value> {
+    let name = name.to_owned();
+    for frame in self.frames.rev_iter() {
+      match frame.find(&name) {
+        Some(value) => { return Some(value); },
+        None        => {}
+      }
+    }
+    None
+  }
+}
@@ -1,11 +1,25 @@
 extern crate foo;
+extern crate bar;
 use std::io::stdio::stdout;
 use std::io::BufferedWriter;
 use foo::Template;
 
 #[test]
 fn test_execution_of_produce_file() {
-  foo::Template(&mut BufferedWriter::new(~stdout() as ~Writer), 4);
+  foo::Template(&mut BufferedWriter::new(~stdout() as ~Writer), 4).unwrap();
+}
+
+#[test]
+fn test_execution_of_to_string() {
+  // Rendered twice, so the second render is sized from the first one
+  assert!(bar::Greeting_to_string("<ers>").unwrap() == ~"Hello, &lt;ers&gt;!");
+  assert!(bar::Greeting_to_string("you").unwrap() == ~"Hello, you!");
+}
+
+#[test]
+fn test_execution_of_to_string_with_invalid_utf8() {
+  assert!(bar::Bytes_to_string(bytes!("ok")).unwrap() == ~"ok");
+  assert!(bar::Bytes_to_string([0xff]).is_err());
 }
 
 #[test]
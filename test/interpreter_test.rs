@@ -0,0 +1,213 @@
+extern crate ers;
+extern crate collections;
+
+use std::str;
+use std::io::MemWriter;
+use std::io::fs::File;
+use collections::TreeMap;
+use ers::{Parser, Interpreter};
+use ers::value::{Value, Context, Number, String, List, Object};
+use ers::{Template, Block, Pos, Location};
+use ers::blocks::{Declaration, If, Text};
+use ers::interpreter::{RenderError, InvalidTemplate, InvalidExpression, UnsupportedCode, UndefinedVariable, UnknownFunction, TooDeep};
+use ers::parser::UnbalancedBlock;
+
+fn render_function(source: &str, name: Option<&str>, context: &mut Context) -> Result<~str, RenderError> {
+  let template = match Parser::new().parse_str(~"inline.ers", source) {
+    Ok(template) => template,
+    Err(error)   => fail!("{}", error)
+  };
+  let interpreter = Interpreter::new(&template);
+  let mut writer  = MemWriter::new();
+
+  try!(match name {
+    Some(name) => interpreter.render_function(name, context, &mut writer),
+    None       => interpreter.render(context, &mut writer)
+  });
+  Ok(str::from_utf8_owned(writer.unwrap()).unwrap())
+}
+
+fn render(source: &str, context: &mut Context) -> Result<~str, RenderError> {
+  render_function(source, None, context)
+}
+
+fn user(name: &str, admin: bool) -> Value {
+  let mut fields = TreeMap::new();
+  fields.insert(~"name", String(name.to_owned()));
+  fields.insert(~"admin", ers::value::Boolean(admin));
+  Object(fields)
+}
+
+#[test]
+fn test_interpreter_render_loop() {
+  let mut context = Context::new();
+  context.set(~"title", String(~"<Users>"));
+  context.set(~"users", List(~[user("Ann", true), user("Bob", false)]));
+
+  let source = "<%! pub fn Users(writer: &mut Writer, title: &str, users: &[User]) %>\
+<h1><%= title %></h1><% for user in users.iter() %><li><%= user.name %>\
+<% if user.admin %>*<% end %></li><% end %><%= users.len() %>";
+
+  match render(source, &mut context) {
+    Ok(output) => assert!(str::eq(&output, &~"<h1>&lt;Users&gt;</h1><li>Ann*</li><li>Bob</li>2"), output),
+    Err(error) => fail!("{}", error)
+  }
+  assert!(context.get("user").is_none());
+}
+
+#[test]
+fn test_interpreter_render_branches() {
+  let source = "<%! pub fn Count(writer: &mut Writer, n: int) %>\
+<% if n == 0 %>none<% elsif n < 3 && n > 0 %>few<% else %>many<% end %>";
+
+  for &(n, expected) in [(0.0, "none"), (2.0, "few"), (7.0, "many")].iter() {
+    let mut context = Context::new();
+    context.set(~"n", Number(n));
+
+    match render(source, &mut context) {
+      Ok(output) => assert!(str::eq(&output, &expected.to_owned()), output),
+      Err(error) => fail!("{}", error)
+    }
+  }
+}
+
+#[test]
+fn test_interpreter_render_expressions() {
+  let mut context = Context::new();
+  context.set(~"items", List(~[Number(1.0), Number(2.0)]));
+
+  let source = "<%! pub fn Expr(writer: &mut Writer, items: ~[int]) %>\
+<%= items[1] * 10 + 1 %>|<%== \"<b>\" %>|<%? \"q\" %>|<% for i in range(0, 3) %><%= i %><% end %>|<%= !items.is_empty() %>";
+
+  match render(source, &mut context) {
+    Ok(output) => assert!(str::eq(&output, &~"21|<b>|&quot;q&quot;|012|true"), output),
+    Err(error) => fail!("{}", error)
+  }
+}
+
+#[test]
+fn test_interpreter_render_partial() {
+  let mut context = Context::new();
+  context.set(~"users", List(~[user("Ann", true)]));
+
+  let source = "<%! pub fn Card(writer: &mut Writer, user: &User, n: int) %>[<%= user.name %> <%= n %>]\
+<%! pub fn Page(writer: &mut Writer, users: &[User]) %><% for user in users.iter() %><%> Card(user, 1) %><% end %>";
+
+  match render_function(source, Some("Page"), &mut context) {
+    Ok(output) => assert!(str::eq(&output, &~"[Ann 1]"), output),
+    Err(error) => fail!("{}", error)
+  }
+}
+
+#[test]
+fn test_interpreter_render_partial_with_writer_argument() {
+  // Declared for a generic writer, so `writer` is a plain argument
+  let source = "<%! pub fn Byline(writer: &str) %>by <%= writer %>\
+<%! pub fn Post(writer: &mut Writer) %><%> Byline(\"Ann\") %>";
+
+  match render_function(source, Some("Post"), &mut Context::new()) {
+    Ok(output) => assert!(str::eq(&output, &~"by Ann"), output),
+    Err(error) => fail!("{}", error)
+  }
+}
+
+#[test]
+fn test_interpreter_render_recursive_partial() {
+  let source = "<%! pub fn Nest(writer: &mut Writer, n: int) %>(<%> Nest(n + 1) %>)";
+  let mut context = Context::new();
+  context.set(~"n", Number(0.0));
+
+  match render(source, &mut context) {
+    Err(error) => assert!(error.kind == TooDeep(~"Nest"), format!("{}", error)),
+    Ok(_)      => fail!("rendered a partial calling itself forever")
+  }
+}
+
+#[test]
+fn test_interpreter_render_brace_code() {
+  let source = "<%! pub fn Page(writer: &mut Writer) %>\n<% if true { %>yes<% } %>";
+
+  match render(source, &mut Context::new()) {
+    Err(error) => {
+      assert!(error.kind == UnsupportedCode(~"if true {"), format!("{}", error));
+      assert!(error.line_no == 2 && error.column == 1);
+    },
+    Ok(_) => fail!("rendered Rust braces")
+  }
+}
+
+#[test]
+fn test_interpreter_render_unbalanced_template() {
+  let template = Template::new(~"page.ers", ~[
+                               ~Block{class: Declaration, content: ~"pub fn Page(writer: &mut Writer)", pos: Pos::new(Location::new(1, 1, 0), Location::new(1, 1, 0)), path: ~"page.ers"},
+                               ~Block{class: If,          content: ~"true", pos: Pos::new(Location::new(2, 3, 0), Location::new(2, 3, 0)), path: ~"page.ers"},
+                               ~Block{class: Text,        content: ~"yes",  pos: Pos::new(Location::new(2, 9, 0), Location::new(2, 9, 0)), path: ~"page.ers"}
+                               ]);
+
+  match Interpreter::new(&template).render(&mut Context::new(), &mut MemWriter::new()) {
+    Err(error) => {
+      assert!(error.kind == InvalidTemplate(UnbalancedBlock(~"`if` is never closed by `end`")), format!("{}", error));
+      assert!(error.line_no == 2 && error.column == 3);
+    },
+    Ok(_) => fail!("rendered an unclosed block")
+  }
+}
+
+#[test]
+fn test_interpreter_render_errors() {
+  let source = "<%! pub fn Page(writer: &mut Writer) %><% let x = 1; %>";
+  match render(source, &mut Context::new()) {
+    Err(error) => assert!(error.kind == UnsupportedCode(~"let x = 1;"), format!("{}", error)),
+    Ok(_)      => fail!("rendered unsupported code")
+  }
+
+  let source = "<%! pub fn Page(writer: &mut Writer) %><%= missing %>";
+  match render(source, &mut Context::new()) {
+    Err(error) => assert!(error.kind == UndefinedVariable(~"missing"), format!("{}", error)),
+    Ok(_)      => fail!("rendered an undefined variable")
+  }
+
+  match render_function(source, Some("Other"), &mut Context::new()) {
+    Err(error) => assert!(error.kind == UnknownFunction(~"Other"), format!("{}", error)),
+    Ok(_)      => fail!("rendered an unknown function")
+  }
+
+  let source = "<%! pub fn Page(writer: &mut Writer) %><% for i in range(0, 1000000000000) %><% end %>";
+  match render(source, &mut Context::new()) {
+    Err(error) => assert!(error.kind == InvalidExpression(~"`range` produces at most 1000000 items"), format!("{}", error)),
+    Ok(_)      => fail!("rendered a range too long to build")
+  }
+}
+
+#[test]
+fn test_interpreter_render_json() {
+  let template = match Parser::new().parse_path(~"test/fixtures/render/users.ers") {
+    Ok(template) => template,
+    Err(error)   => fail!("{}", error)
+  };
+  let data = File::open(&Path::new("test/fixtures/render/users.json")).read_to_str().unwrap();
+  let mut context = Context::from_json(data.as_slice()).unwrap();
+  let mut writer  = MemWriter::new();
+
+  match Interpreter::new(&template).render(&mut context, &mut writer) {
+    Ok(())     => {},
+    Err(error) => fail!("{}", error)
+  }
+
+  let output = str::from_utf8_owned(writer.unwrap()).unwrap();
+  assert!(str::eq(&output, &~"<h1>Team</h1>\n<p>Ann (admin)</p>\n<p>Bob</p>\n"), output);
+}
+
+#[test]
+fn test_interpreter_render_control_flow_tags() {
+  let mut context = Context::new();
+  context.set(~"users", List(~[user("Ann", true), user("Bob", false)]));
+
+  let source = "<%! pub fn Users(writer: &mut Writer, users: &[User]) %>\
+<% for user in users %><% if user.admin %>[<%= user.name %>]<% elsif user.name == \"Bob\" %>(<%= user.name %>)<% else %>?<% end %><% end %>";
+
+  match render(source, &mut context) {
+    Ok(output) => assert!(str::eq(&output, &~"[Ann](Bob)"), output),
+    Err(error) => fail!("{}", error)
+  }
+}
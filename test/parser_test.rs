@@ -1,7 +1,13 @@
 extern crate ers;
 
+use std::io::MemReader;
 use ers::Parser;
-use ers::blocks::{Text,Header,Declaration,Code,Print,Pos,Block};
+use ers::parser::{IoFailure, IncludeCycle, IncludeNotFound, UnknownDirective, InvalidDirective, UnbalancedBlock};
+use ers::blocks::{Text,Header,Declaration,Code,Print,Pos,Location,Block};
+
+fn pos(line_no: int, column: int, offset: uint, end_line_no: int, end_column: int, end_offset: uint) -> Pos {
+  Pos::new(Location::new(line_no, column, offset), Location::new(end_line_no, end_column, end_offset))
+}
 
 #[test]
 fn test_parser_parsepath() {
@@ -10,29 +16,228 @@ fn test_parser_parsepath() {
   let templ  = parser.parse_path(path.clone());
 
   match templ {
-    None => fail!("Empty template"),
-    Some(template) => {
+    Err(error) => fail!("{}", error),
+    Ok(template) => {
       let ref bs = template.blocks;
 
       assert!(std::str::eq(&path.clone(), &template.path));
       assert!(bs.len()>0);
 
-      assert!(bs[0]  == ~Block{class: Declaration, content: ~" pub fn Template(writer: &mut Writer, i: int) ", pos: Pos{line_no: 1}});
-      assert!(bs[1]  == ~Block{class: Text,        content: ~"\n",                                             pos: Pos{line_no: 2}});
-      assert!(bs[2]  == ~Block{class: Header,      content: ~" use std::io; ",                                 pos: Pos{line_no: 2}});
-      assert!(bs[3]  == ~Block{class: Text,        content: ~"\n",                                             pos: Pos{line_no: 3}});
-      assert!(bs[4]  == ~Block{class: Text,        content: ~"<html>\n",                                       pos: Pos{line_no: 4}});
-      assert!(bs[5]  == ~Block{class: Code,        content: ~" for n in range(0, i - 1) { ",                   pos: Pos{line_no: 4}});
-      assert!(bs[6]  == ~Block{class: Text,        content: ~"\n",                                             pos: Pos{line_no: 5}});
-      assert!(bs[7]  == ~Block{class: Text,        content: ~"<p class=\"foobar",                              pos: Pos{line_no: 5}});
-      assert!(bs[8]  == ~Block{class: Print,       content: ~" n ",                                            pos: Pos{line_no: 5}});
-      assert!(bs[9]  == ~Block{class: Text,        content: ~"\">\n  ",                                        pos: Pos{line_no: 6}});
-      assert!(bs[10] == ~Block{class: Print,       content: ~" n + 1 ",                                        pos: Pos{line_no: 6}});
-      assert!(bs[11] == ~Block{class: Text,        content: ~"\n",                                             pos: Pos{line_no: 7}});
-      assert!(bs[12] == ~Block{class: Text,        content: ~"</p>\n",                                         pos: Pos{line_no: 8}});
-      assert!(bs[13] == ~Block{class: Code,        content: ~" } ",                                            pos: Pos{line_no: 8}});
-      assert!(bs[14] == ~Block{class: Text,        content: ~"\n",                                             pos: Pos{line_no: 9}});
-      assert!(bs[15] == ~Block{class: Text,        content: ~"</html>\n",                                      pos: Pos{line_no: 10}});
+      assert!(bs[0]  == ~Block{class: Declaration, content: ~" pub fn Template(writer: &mut Writer, i: int) ", pos: pos( 1,  1,   0,  1, 52,  51), path: path.clone()});
+      assert!(bs[1]  == ~Block{class: Text,        content: ~"\n",                                             pos: pos( 1, 52,  51,  2,  1,  52), path: path.clone()});
+      assert!(bs[2]  == ~Block{class: Header,      content: ~" use std::io; ",                                 pos: pos( 2,  1,  52,  2, 21,  72), path: path.clone()});
+      assert!(bs[3]  == ~Block{class: Text,        content: ~"\n",                                             pos: pos( 2, 21,  72,  3,  1,  73), path: path.clone()});
+      assert!(bs[4]  == ~Block{class: Text,        content: ~"<html>\n",                                       pos: pos( 3,  1,  73,  4,  1,  80), path: path.clone()});
+      assert!(bs[5]  == ~Block{class: Code,        content: ~" for n in range(0, i - 1) { ",                   pos: pos( 4,  1,  80,  4, 33, 112), path: path.clone()});
+      assert!(bs[6]  == ~Block{class: Text,        content: ~"\n",                                             pos: pos( 4, 33, 112,  5,  1, 113), path: path.clone()});
+      assert!(bs[7]  == ~Block{class: Text,        content: ~"<p class=\"foobar",                              pos: pos( 5,  1, 113,  5, 17, 129), path: path.clone()});
+      assert!(bs[8]  == ~Block{class: Print,       content: ~" n ",                                            pos: pos( 5, 17, 129,  5, 25, 137), path: path.clone()});
+      assert!(bs[9]  == ~Block{class: Text,        content: ~"\">\n  ",                                        pos: pos( 5, 25, 137,  6,  3, 142), path: path.clone()});
+      assert!(bs[10] == ~Block{class: Print,       content: ~" n + 1 ",                                        pos: pos( 6,  3, 142,  6, 15, 154), path: path.clone()});
+      assert!(bs[11] == ~Block{class: Text,        content: ~"\n",                                             pos: pos( 6, 15, 154,  7,  1, 155), path: path.clone()});
+      assert!(bs[12] == ~Block{class: Text,        content: ~"</p>\n",                                         pos: pos( 7,  1, 155,  8,  1, 160), path: path.clone()});
+      assert!(bs[13] == ~Block{class: Code,        content: ~" } ",                                            pos: pos( 8,  1, 160,  8,  8, 167), path: path.clone()});
+      assert!(bs[14] == ~Block{class: Text,        content: ~"\n",                                             pos: pos( 8,  8, 167,  9,  1, 168), path: path.clone()});
+      assert!(bs[15] == ~Block{class: Text,        content: ~"</html>\n",                                      pos: pos( 9,  1, 168, 10,  1, 176), path: path.clone()});
+    }
+  }
+}
+
+#[test]
+fn test_parser_parse_str() {
+  let templ = Parser::new().parse_str(~"inline.ers", "<%! pub fn Inline(writer: &mut Writer) %>hi");
+
+  match templ {
+    Err(error) => fail!("{}", error),
+    Ok(template) => {
+      let ref bs = template.blocks;
+
+      assert!(std::str::eq(&~"inline.ers", &template.path));
+      assert!(bs.len() == 2);
+      assert!(bs[0] == ~Block{class: Declaration, content: ~" pub fn Inline(writer: &mut Writer) ", pos: pos(1,  1,  0, 1, 42, 41), path: ~"inline.ers"});
+      assert!(bs[1] == ~Block{class: Text,        content: ~"hi",                                   pos: pos(1, 42, 41, 1, 44, 43), path: ~"inline.ers"});
+    }
+  }
+}
+
+#[test]
+fn test_parser_parse_reader() {
+  let mut reader = MemReader::new("<%= n %>\n".as_bytes().to_owned());
+  let templ      = Parser::new().parse_reader(~"reader.ers", &mut reader);
+
+  match templ {
+    Err(error) => fail!("{}", error),
+    Ok(template) => {
+      assert!(template.blocks.len() == 2);
+      assert!(template.blocks[0].class == Print);
+    }
+  }
+}
+
+#[test]
+fn test_parser_parse_path_missing_file() {
+  match Parser::new().parse_path(~"test/fixtures/missing.ers") {
+    Err(error) => {
+      match error.kind {
+        IoFailure(_) => assert!(std::str::eq(&~"test/fixtures/missing.ers", &error.path)),
+        _            => fail!("{}", error)
+      }
+    },
+    Ok(_) => fail!("parsed a missing file")
+  }
+}
+
+#[test]
+fn test_parser_parse_path_include() {
+  let path  = ~"test/fixtures/include/page.ers";
+  let templ = Parser::new().parse_path(path.clone());
+
+  match templ {
+    Err(error) => fail!("{}", error),
+    Ok(template) => {
+      let ref bs = template.blocks;
+      let header = ~"test/fixtures/include/partials/header.ers";
+
+      assert!(bs.len() == 6);
+      assert!(bs[0].class == Declaration && std::str::eq(&bs[0].path, &path));
+      assert!(bs[1].class == Text        && std::str::eq(&bs[1].path, &path));
+      assert!(bs[2] == ~Block{class: Text, content: ~"<h1>title", pos: pos(1,  1, 0, 1, 10,  9), path: header.clone()});
+      assert!(bs[3] == ~Block{class: Text, content: ~"</h1>\n",   pos: pos(1, 10, 9, 2,  1, 15), path: header.clone()});
+      assert!(bs[4].class == Text        && std::str::eq(&bs[4].path, &path));
+      assert!(bs[5].class == Text        && std::str::eq(&bs[5].content, &~"<p>body</p>\n"));
     }
   }
 }
+
+#[test]
+fn test_parser_parse_path_include_cycle() {
+  match Parser::new().parse_path(~"test/fixtures/include/cycle_a.ers") {
+    Err(error) => {
+      assert!(error.kind == IncludeCycle(~"test/fixtures/include/cycle_a.ers"), format!("{}", error));
+      assert!(std::str::eq(&~"test/fixtures/include/cycle_b.ers", &error.path));
+      assert!(error.line_no == 1 && error.column == 1);
+    },
+    Ok(_) => fail!("parsed an include cycle")
+  }
+}
+
+#[test]
+fn test_parser_parse_path_include_not_found() {
+  match Parser::new().parse_path(~"test/fixtures/include/missing.ers") {
+    Err(error) => assert!(error.kind == IncludeNotFound(~"test/fixtures/include/nope.ers"), format!("{}", error)),
+    Ok(_)      => fail!("parsed a missing include")
+  }
+}
+
+fn render(blocks: &[~Block]) -> ~str {
+  let mut output = ~"";
+
+  for block in blocks.iter() {
+    match block.class {
+      Text  => output.push_str(block.content.as_slice()),
+      Print => output.push_str(format!("<%={}%>", block.content)),
+      _     => {}
+    }
+  }
+  output
+}
+
+#[test]
+fn test_parser_parse_path_layout() {
+  let path   = ~"test/fixtures/layout/page.ers";
+  let layout = ~"test/fixtures/layout/application.ers";
+
+  match Parser::new().parse_path(path.clone()) {
+    Err(error) => fail!("{}", error),
+    Ok(template) => {
+      let ref bs = template.blocks;
+
+      assert!(bs[0].class == Declaration && std::str::eq(&bs[0].path, &path));
+      assert!(bs[1].class == Header      && std::str::eq(&bs[1].path, &layout));
+      assert!(bs.iter().any(|b| std::str::eq(&b.content, &~"Hello") && std::str::eq(&b.path, &path)));
+
+      let output = render(bs.as_slice());
+      assert!(std::str::eq(&output, &~"<html><head><title>Hello</title></head>\n<body><p>Hi <%= name %></p>\n</body></html>\n"), output);
+    }
+  }
+}
+
+#[test]
+fn test_parser_parse_str_unclosed_slot() {
+  match Parser::new().parse_str(~"inline.ers", "<%@ layout \"app.ers\" %><%@ slot \"title\" %>Hello") {
+    Err(error) => assert!(error.kind == InvalidDirective(~"`slot` is never closed by `end`"), format!("{}", error)),
+    Ok(_)      => fail!("parsed an unclosed slot")
+  }
+}
+
+#[test]
+fn test_parser_parse_str_slot_without_layout() {
+  match Parser::new().parse_str(~"inline.ers", "<%@ slot \"title\" %>Hello<%@ end %>") {
+    Err(error) => assert!(error.line_no == 1 && error.column == 1, format!("{}", error)),
+    Ok(_)      => fail!("parsed a slot without a layout")
+  }
+}
+
+#[test]
+fn test_parser_parse_str_include_directory() {
+  match Parser::new().parse_str(~"test/fixtures/include/page.ers", "<%@ include \"partials\" %>") {
+    Err(error) => {
+      match error.kind {
+        IoFailure(_) => assert!(std::str::eq(&~"test/fixtures/include/partials", &error.path)),
+        _            => fail!("{}", error)
+      }
+    },
+    Ok(_) => fail!("included a directory")
+  }
+}
+
+#[test]
+fn test_parser_parse_str_unknown_directive() {
+  match Parser::new().parse_str(~"inline.ers", "<%@ frobnicate %>") {
+    Err(error) => assert!(error.kind == UnknownDirective(~"frobnicate"), format!("{}", error)),
+    Ok(_)      => fail!("parsed an unknown directive")
+  }
+}
+
+#[test]
+fn test_parser_parse_str_unquoted_include() {
+  match Parser::new().parse_str(~"inline.ers", "<%@ include header.ers %>") {
+    Err(error) => {
+      match error.kind {
+        InvalidDirective(_) => {},
+        _                   => fail!("{}", error)
+      }
+    },
+    Ok(_) => fail!("parsed an unquoted include")
+  }
+}
+
+fn unbalanced(source: &str) -> (~str, int, int) {
+  match Parser::new().parse_str(~"inline.ers", source) {
+    Err(error) => {
+      match error.kind {
+        UnbalancedBlock(ref message) => (message.clone(), error.line_no, error.column),
+        _                            => fail!("{}", error)
+      }
+    },
+    Ok(_) => fail!("parsed unbalanced blocks")
+  }
+}
+
+#[test]
+fn test_parser_parse_str_unbalanced_blocks() {
+  let (message, line_no, column) = unbalanced("<%! pub fn A() %>\n  <% if a %>\n<% for x in xs %><% end %>");
+  assert!(std::str::eq(&message, &~"`if` is never closed by `end`") && line_no == 2 && column == 3, message);
+
+  let (message, line_no, column) = unbalanced("<%! pub fn A() %>\n<% end %>");
+  assert!(std::str::eq(&message, &~"`end` has no `if` or `for` to belong to") && line_no == 2 && column == 1, message);
+
+  let (message, line_no, _) = unbalanced("<% if a %><% else %>\n<% else %><% end %>");
+  assert!(std::str::eq(&message, &~"`else` after `else`") && line_no == 2, message);
+
+  let (message, _, _) = unbalanced("<% for x in xs %><% elsif a %><% end %>");
+  assert!(std::str::eq(&message, &~"`elsif` in a `for` loop"), message);
+
+  let (message, line_no, _) = unbalanced("<%! pub fn A() %><% if a %>\n<%! pub fn B() %><% end %>");
+  assert!(std::str::eq(&message, &~"`if` is never closed by `end`") && line_no == 1, message);
+}
@@ -0,0 +1,35 @@
+extern crate ers;
+
+use std::str::eq;
+use std::sync::atomics::{AtomicUint, INIT_ATOMIC_UINT, Relaxed};
+use ers::runtime::{escape_html, size_hint, learn_size};
+
+#[test]
+fn test_runtime_escape_html() {
+  assert!(eq(&~"&lt;a href=&quot;/?x=1&amp;y=2&quot;&gt;it&#39;s&lt;/a&gt;",
+             &escape_html("<a href=\"/?x=1&y=2\">it's</a>")));
+}
+
+#[test]
+fn test_runtime_escape_html_plain_text() {
+  assert!(eq(&~"hello world", &escape_html("hello world")));
+}
+
+#[test]
+fn test_runtime_escape_html_multibyte() {
+  assert!(eq(&~"café &amp; crème", &escape_html("café & crème")));
+}
+
+#[test]
+fn test_runtime_size_hint() {
+  let mut learned : AtomicUint = INIT_ATOMIC_UINT;
+
+  assert!(size_hint(100, &learned) == 100);
+
+  learn_size(&mut learned, 400);
+  assert!(learned.load(Relaxed) == 400);
+  assert!(size_hint(100, &learned) == 400);
+
+  learn_size(&mut learned, 800);
+  assert!(learned.load(Relaxed) == 500);
+}
@@ -1,179 +1,405 @@
 extern crate ers;
 
-use std::io::BufferedReader;
-use std::io::fs::File;
 use std::str::eq;
-use ers::{Scanner};
+use ers::{Scanner, Syntax, Location};
+use ers::parser::{ParseError, ParseErrorKind};
+use ers::parser::{UnterminatedCodeBlock, UnterminatedHeader, NewlineInHeader, UnknownTag};
+use ers::parser::{InvalidSyntaxDirective, InvalidSyntax, InvalidPartial};
 
-fn fail_with_file(path : ~str) {
-  let mut buf  = ~BufferedReader::new(File::open(&Path::new(path.clone()))) as ~Buffer;
-  let input    = buf.read_to_str().unwrap();
-  let peekable = input.chars();
-
-  let mut s = Scanner::new(peekable, path.clone());
+fn scan_error(input : &str) -> ParseError {
+  let mut s = Scanner::new(input.chars(), ~"test.ers");
 
   match s.scan() {
-    None => assert!(true),
-    Some(b) => assert!(false, b.content)
+    Err(error)    => error,
+    Ok(None)      => fail!("no error"),
+    Ok(Some(b))   => fail!("unexpected block {}", b.content)
   }
 }
 
+fn fail_with(input : &str, kind: ParseErrorKind) {
+  let error = scan_error(input);
+
+  assert!(error.kind == kind, format!("{}", error));
+}
+
 #[test]
 fn test_scanner_scan_text_block() {
-  let path = ~"test/fixtures/scan_text_block.ers";
-  let mut buf  = ~BufferedReader::new(File::open(&Path::new(path.clone()))) as ~Buffer;
-  let input    = buf.read_to_str().unwrap();
-  let peekable = input.chars();
-  let mut s = Scanner::new(peekable, path.clone());
-  let b = s.scan().unwrap();
+  let mut s = Scanner::new("hello world\n".chars(), ~"test.ers");
+  let b = s.scan().unwrap().unwrap();
 
   assert!(std::str::eq(&b.content, &~"hello world\n"));
-  assert!(b.pos.line_no == 2);
+  assert!(b.pos.start == Location::new(1, 1, 0));
+  assert!(b.pos.end   == Location::new(2, 1, 12));
 }
 
 #[test]
 fn test_scanner_scan_text_block_single_lt() {
-  let path = ~"test/fixtures/single_lt.ers";
-  let mut buf  = ~BufferedReader::new(File::open(&Path::new(path.clone()))) as ~Buffer;
-  let input    = buf.read_to_str().unwrap();
-  let peekable = input.chars();
-  let mut s = Scanner::new(peekable, path.clone());
-  let b = s.scan().unwrap();
+  let mut s = Scanner::new("<\n".chars(), ~"test.ers");
+  let b = s.scan().unwrap().unwrap();
 
   assert!(std::str::eq(&b.content, &~"<\n"));
 }
 
 #[test]
 fn test_scanner_scan_text_block_starting_lt() {
-  let path = ~"test/fixtures/starting_lt.ers";
-  let mut buf  = ~BufferedReader::new(File::open(&Path::new(path.clone()))) as ~Buffer;
-  let input    = buf.read_to_str().unwrap();
-  let peekable = input.chars();
-  let mut s    = Scanner::new(peekable, path.clone());
-  let b = s.scan().unwrap();
+  let mut s = Scanner::new("<html>\n".chars(), ~"test.ers");
+  let b = s.scan().unwrap().unwrap();
 
   assert!(std::str::eq(&b.content, &~"<html>\n"));
 }
 
 #[test]
 fn test_scanner_scan_code_block() {
-  let path = ~"test/fixtures/code_block.ers";
-  let mut buf  = ~BufferedReader::new(File::open(&Path::new(path.clone()))) as ~Buffer;
-  let input    = buf.read_to_str().unwrap();
-  let peekable = input.chars();
-  let mut s    = Scanner::new(peekable, path.clone());
-  let b = s.scan().unwrap();
+  let mut s = Scanner::new("<% let x = 1 %>\n".chars(), ~"test.ers");
+  let b = s.scan().unwrap().unwrap();
 
   assert!(std::str::eq(&b.content, &~" let x = 1 "), b.content)
 }
 
+#[test]
+fn test_scanner_scan_code_block_span() {
+  let mut s = Scanner::new("<% let x = 1 %>\n".chars(), ~"test.ers");
+  let b = s.scan().unwrap().unwrap();
+
+  assert!(b.pos.start == Location::new(1, 1, 0));
+  assert!(b.pos.end   == Location::new(1, 16, 15));
+}
+
 #[test]
 fn test_scanner_scan_code_block_unexpected_eof_1() {
-  fail_with_file(~"test/fixtures/code_block_unexpected_eof_1.ers");
+  fail_with("<%\n", UnterminatedCodeBlock(~"%>"));
 }
 
 #[test]
 fn test_scanner_scan_code_block_unexpected_eof_2() {
-  fail_with_file(~"test/fixtures/code_block_unexpected_eof_2.ers");
+  fail_with("<% let x = 2\n", UnterminatedCodeBlock(~"%>"));
 }
 
 #[test]
 fn test_scanner_scan_code_block_unexpected_eof_3() {
-  fail_with_file(~"test/fixtures/code_block_unexpected_eof_3.ers");
+  fail_with("<% let x = 2 %\n", UnterminatedCodeBlock(~"%>"));
 }
 
 #[test]
 fn test_scanner_scan_code_block_unexpected_eof_4() {
-  fail_with_file(~"test/fixtures/code_block_unexpected_eof_4.ers");
+  fail_with("<% x = 2 \n", UnterminatedCodeBlock(~"%>"));
 }
 
 #[test]
 fn test_scanner_scan_header_block() {
-  let path = ~"test/fixtures/header_block.ers";
-  let mut buf  = ~BufferedReader::new(File::open(&Path::new(path.clone()))) as ~Buffer;
-  let input    = buf.read_to_str().unwrap();
-  let peekable = input.chars();
-  let mut s = Scanner::new(peekable, path.clone());
-  let b = s.scan().unwrap();
+  let mut s = Scanner::new("<%% use std::io %%>\n".chars(), ~"test.ers");
+  let b = s.scan().unwrap().unwrap();
 
   assert!(std::str::eq(&b.content, &~" use std::io "), b.content);
 }
 
 #[test]
 fn test_scanner_scan_header_block_unexpected_eof_1() {
-  fail_with_file(~"test/fixtures/header_block_unexpected_eof_1.ers");
+  fail_with("<%% use std::io \n", NewlineInHeader);
 }
 
 #[test]
 fn test_scanner_scan_header_block_unexpected_eof_2() {
-  fail_with_file(~"test/fixtures/header_block_unexpected_eof_2.ers");
+  fail_with("<%% use std::io %\n", NewlineInHeader);
 }
 
 #[test]
 fn test_scanner_scan_header_block_unexpected_eof_3() {
-  fail_with_file(~"test/fixtures/header_block_unexpected_eof_3.ers");
+  fail_with("<%% use std::io % \n", NewlineInHeader);
 }
 
 #[test]
 fn test_scanner_scan_header_block_unexpected_eof_4() {
-  fail_with_file(~"test/fixtures/header_block_unexpected_eof_4.ers");
+  fail_with("<%% use std::io %%\n", NewlineInHeader);
 }
 
 #[test]
 fn test_scanner_scan_header_block_unexpected_eof_5() {
-  fail_with_file(~"test/fixtures/header_block_unexpected_eof_5.ers");
+  fail_with("<%% use std::io %% \n", NewlineInHeader);
+}
+
+#[test]
+fn test_scanner_scan_header_block_unterminated() {
+  fail_with("<%% use std::io", UnterminatedHeader(~"%%>"));
+}
+
+#[test]
+fn test_scanner_scan_partial_block() {
+  let mut s = Scanner::new("<%> user_card(&user) %>\n".chars(), ~"test.ers");
+  let b = s.scan().unwrap().unwrap();
+
+  assert!(b.class == ers::blocks::Partial);
+  assert!(std::str::eq(&b.content, &~"user_card(&user)"), b.content);
+}
+
+#[test]
+fn test_scanner_scan_control_flow_tags() {
+  let mut s = Scanner::new("<% if n > 0 %><% elsif n < 0 %><% else %><% for x in xs.iter() %><% end %><% if n > 0 { %>".chars(), ~"test.ers");
+  let expected = [(ers::blocks::If,    "n > 0"),
+                  (ers::blocks::Elsif, "n < 0"),
+                  (ers::blocks::Else,  ""),
+                  (ers::blocks::For,   "x in xs.iter()"),
+                  (ers::blocks::End,   ""),
+                  (ers::blocks::Code,  " if n > 0 { ")];
+
+  for &(class, content) in expected.iter() {
+    let b = s.scan().unwrap().unwrap();
+    assert!(b.class == class && std::str::eq(&b.content, &content.to_owned()), b.content);
+  }
+}
+
+#[test]
+fn test_scanner_scan_brace_balanced_code() {
+  let mut s = Scanner::new("<% if debug { log() } %><% for x in xs.iter() { f(x) } %>".chars(), ~"test.ers");
+
+  for content in [" if debug { log() } ", " for x in xs.iter() { f(x) } "].iter() {
+    let b = s.scan().unwrap().unwrap();
+    assert!(b.class == ers::blocks::Code && std::str::eq(&b.content, &content.to_owned()), b.content);
+  }
+}
+
+#[test]
+fn test_scanner_scan_invalid_partial() {
+  fail_with("<%> note %>\n", InvalidPartial(~"note"));
+}
+
+#[test]
+fn test_scanner_scan_invalid_partial_position() {
+  let mut s = Scanner::new("<p>\n  <%> user_card %>".chars(), ~"test.ers");
+  s.scan().unwrap();
+
+  match s.scan() {
+    Err(error) => {
+      assert!(error.kind == InvalidPartial(~"user_card"), format!("{}", error));
+      assert!(error.line_no == 2 && error.column == 3);
+    },
+    Ok(_) => fail!("scanned a partial without a call")
+  }
+}
+
+#[test]
+fn test_scanner_scan_error_position() {
+  let mut s = Scanner::new("hello\n  <%> note %>\n".chars(), ~"test.ers");
+
+  s.scan().unwrap();
+  match s.scan() {
+    Err(error) => {
+      assert!(std::str::eq(&error.path, &~"test.ers"));
+      assert!(error.line_no == 2);
+      assert!(error.column == 3);
+    },
+    Ok(_) => fail!("no error")
+  }
 }
 
 #[test]
 fn test_scanner_scan_print_block() {
-  let path = ~"test/fixtures/print_block.ers";
-  let mut buf  = ~BufferedReader::new(File::open(&Path::new(path.clone()))) as ~Buffer;
-  let input    = buf.read_to_str().unwrap();
-  let peekable = input.chars();
-  let mut s = Scanner::new(peekable, path.clone());
-  let b = s.scan().unwrap();
+  let mut s = Scanner::new("<%= myNum %>\n".chars(), ~"test.ers");
+  let b = s.scan().unwrap().unwrap();
 
   assert!(std::str::eq(&b.content, &~" myNum "), b.content);
 }
 
+#[test]
+fn test_scanner_scan_raw_block() {
+  let mut s = Scanner::new("<%== html %>\n".chars(), ~"test.ers");
+  let b = s.scan().unwrap().unwrap();
+
+  assert!(b.class == ers::blocks::Raw);
+  assert!(std::str::eq(&b.content, &~" html "), b.content);
+}
+
+
+#[test]
+fn test_scanner_scan_debug_block() {
+  let mut s = Scanner::new("<%? user %>\n".chars(), ~"test.ers");
+  let b = s.scan().unwrap().unwrap();
+
+  assert!(b.class == ers::blocks::Debug);
+  assert!(std::str::eq(&b.content, &~" user "), b.content);
+}
+
+#[test]
+fn test_scanner_scan_comment_block() {
+  let mut s = Scanner::new("<p>\n  <%# TODO: drop this paragraph %>\n".chars(), ~"test.ers");
+  s.scan().unwrap();
+  let b = s.scan().unwrap().unwrap();
+
+  assert!(b.class == ers::blocks::Comment);
+  assert!(std::str::eq(&b.content, &~" TODO: drop this paragraph "), b.content);
+  assert!(b.pos.start == Location::new(2, 3, 6));
+}
 
 #[test]
 fn test_scanner_scan_print_block_unexpected_eof() {
-  fail_with_file(~"test/fixtures/print_block_unexpected_eof.ers");
+  fail_with("<%= \n", UnterminatedCodeBlock(~"%>"));
+}
+
+fn scan_all(input: &str) -> ~[~str] {
+  let mut s      = Scanner::new(input.chars(), ~"test.ers");
+  let mut output = ~[];
+
+  loop {
+    match s.scan() {
+      Ok(Some(b)) => output.push(b.content),
+      Ok(None)    => break,
+      Err(error)  => fail!("{}", error)
+    }
+  }
+  output
+}
+
+#[test]
+fn test_scanner_scan_trim_modifiers() {
+  let contents = scan_all("<ul>\n  <%- for x in xs { -%>\n  <li><%= x %></li>\n  <%- } -%>\n</ul>\n");
+
+  assert!(contents == ~[~"<ul>\n", ~" for x in xs { ", ~"  ", ~"<li>", ~" x ", ~"</li>\n", ~" } ", ~"</ul>\n"], format!("{:?}", contents));
+}
+
+#[test]
+fn test_scanner_scan_trim_modifiers_first_line() {
+  let contents = scan_all("  <%- let x = 1 -%>\r\nend");
+
+  assert!(contents == ~[~" let x = 1 ", ~"end"], format!("{:?}", contents));
+}
+
+#[test]
+fn test_scanner_scan_trim_modifiers_keep_text_before_tag() {
+  let contents = scan_all("a <%- let x = 1 %>\n");
+
+  assert!(contents == ~[~"a ", ~" let x = 1 ", ~"\n"], format!("{:?}", contents));
+}
+
+#[test]
+fn test_scanner_scan_escaped_tag() {
+  let contents = scan_all("<p>Use \\<%= expr %> to print</p>");
+
+  assert!(contents == ~[~"<p>Use <%= expr %> to print", ~"</p>"], format!("{:?}", contents));
+}
+
+#[test]
+fn test_scanner_scan_escaped_header_tag() {
+  let contents = scan_all("\\<%% use std::io; %%>");
+
+  assert!(contents == ~[~"<%% use std::io; %%>"], format!("{:?}", contents));
+}
+
+#[test]
+fn test_scanner_scan_escaped_backslash() {
+  let contents = scan_all("C:\\\\<%= dir %> \\\\x");
+
+  assert!(contents == ~[~"C:\\", ~" dir ", ~" \\\\x"], format!("{:?}", contents));
+}
+
+#[test]
+fn test_scanner_scan_lone_backslash() {
+  let contents = scan_all("a \\ b\\<p>");
+
+  assert!(contents == ~[~"a \\ b\\", ~"<p>"], format!("{:?}", contents));
+}
+
+#[test]
+fn test_scanner_scan_custom_syntax() {
+  let syntax     = Syntax::from_directive("open={{ close=}} header=@ directive=^").unwrap();
+  let mut s      = Scanner::with_syntax("{{@ use std::io; @}}<%= x %>{ {{= x }}{{- y -}}\n".chars(), ~"test.ers", syntax);
+  let mut blocks = ~[];
+
+  loop {
+    match s.scan() {
+      Ok(Some(b)) => blocks.push(b),
+      Ok(None)    => break,
+      Err(error)  => fail!("{}", error)
+    }
+  }
+
+  assert!(blocks.len() == 5);
+  assert!(blocks[0].class == ers::blocks::Header && eq(&blocks[0].content, &~" use std::io; "));
+  assert!(blocks[1].class == ers::blocks::Text   && eq(&blocks[1].content, &~"<%= x %>"));
+  assert!(blocks[2].class == ers::blocks::Text   && eq(&blocks[2].content, &~"{ "));
+  assert!(blocks[3].class == ers::blocks::Print  && eq(&blocks[3].content, &~" x "));
+  assert!(blocks[4].class == ers::blocks::Code   && eq(&blocks[4].content, &~" y "));
+}
+
+#[test]
+fn test_scanner_scan_syntax_directive() {
+  let contents = scan_all("#ers-syntax open=<< close=>>\n\\begin{document}<<= title >>\\<< not code");
+
+  assert!(contents == ~[~"\\begin{document}", ~" title ", ~"<< not code"], format!("{:?}", contents));
+}
+
+#[test]
+fn test_scanner_scan_invalid_syntax_directive() {
+  let error = scan_error("#ers-syntax open=<< print=:=\nhello");
+
+  match error.kind {
+    InvalidSyntaxDirective(_) => assert!(error.line_no == 1 && error.column == 1),
+    _                         => fail!("{}", error)
+  }
+}
+
+#[test]
+fn test_scanner_scan_invalid_syntax() {
+  let mut syntax = Syntax::new();
+  syntax.debug = '=';
+  let mut s = Scanner::with_syntax("<%= x %>".chars(), ~"test.ers", syntax);
+
+  match s.scan() {
+    Err(error) => assert!(error.kind == InvalidSyntax(~"`print` and `debug` are both `=`"), format!("{}", error)),
+    Ok(_)      => fail!("scanned with an ambiguous syntax")
+  }
+}
+
+#[test]
+fn test_scanner_scan_unknown_tag() {
+  fail_with("#ers-syntax partial=&\n<%> footer() %>", UnknownTag('>'));
+  fail_with("#ers-syntax header=$\n<%% use std::io; %%>", UnknownTag('%'));
+}
+
+#[test]
+fn test_scanner_scan_unterminated_custom_syntax() {
+  let error = scan_error("#ers-syntax open=<< close=>>\n<<= x");
+
+  assert!(error.kind == UnterminatedCodeBlock(~">>"), format!("{}", error));
+  assert!(eq(&format!("{}", error.kind), &~"unterminated block, expected `>>`"));
+}
+
+#[test]
+fn test_scanner_scan_header_trim_modifier() {
+  let contents = scan_all("<%% use std::io; -%%>\n<html>");
+
+  assert!(contents == ~[~" use std::io; ", ~"<html>"], format!("{:?}", contents));
 }
 
 #[test]
 fn test_scanner_scan_eof() {
-  fail_with_file(~"test/fixtures/eof.ers");
+  let mut s = Scanner::new("".chars(), ~"test.ers");
+
+  assert!(s.scan() == Ok(None));
 }
 
 #[test]
 fn test_scanner_scan_multiline() {
-  let path = ~"test/fixtures/multiline.ers";
-  let mut buf  = ~BufferedReader::new(File::open(&Path::new(path.clone()))) as ~Buffer;
-  let input    = buf.read_to_str().unwrap();
-  let peekable = input.chars();
-  let mut s = Scanner::new(peekable, path.clone());
+  let mut s = Scanner::new("hello\\nworld<%= x \n \n\n%>goodbye\n".chars(), ~"test.ers");
 
   match s.scan() {
-    None => assert!(false),
-    Some(b) => {
+    Ok(Some(b)) => {
       assert!(b.class == ers::blocks::Text);
-      assert!(std::str::eq(&~"hello\\nworld", &b.content));
-    }
+      assert!(eq(&~"hello\\nworld", &b.content));
+    },
+    _ => assert!(false)
   }
   match s.scan() {
-    None => assert!(false),
-    Some(b) => {
+    Ok(Some(b)) => {
       assert!(b.class == ers::blocks::Print);
-      assert!(std::str::eq(&~" x \n \n\n", &b.content));
-    }
+      assert!(eq(&~" x \n \n\n", &b.content));
+    },
+    _ => assert!(false)
   }
   match s.scan() {
-    None => assert!(false),
-    Some(b) => {
+    Ok(Some(b)) => {
       assert!(b.class == ers::blocks::Text);
-      assert!(std::str::eq(&~"goodbye\n", &b.content));
-    }
+      assert!(eq(&~"goodbye\n", &b.content));
+    },
+    _ => assert!(false)
   }
 }
@@ -0,0 +1,92 @@
+extern crate ers;
+
+use std::str::eq;
+use ers::signature::{Signature, Structure, Param};
+
+#[test]
+fn test_signature_parse() {
+  let signature = Signature::parse(" pub fn Page(writer: &mut Writer, name: &str, items: &[(int, ~str)]) ").unwrap();
+
+  assert!(eq(&~"pub fn", &signature.prefix));
+  assert!(eq(&~"Page", &signature.name));
+  assert!(signature.generics.is_empty());
+  assert!(signature.params == ~[Param{pattern: ~"writer", ty: ~"&mut Writer"},
+                                Param{pattern: ~"name",   ty: ~"&str"},
+                                Param{pattern: ~"items",  ty: ~"&[(int, ~str)]"}]);
+  assert!(signature.output.is_none());
+  assert!(signature.arguments(1) == Some(~"name, items"));
+}
+
+#[test]
+fn test_signature_parse_generics_and_output() {
+  let signature = Signature::parse("fn List<T: Show>(writer: &mut Writer, f: |T| -> uint, m: std::hashmap::HashMap<~str, T>) -> IoResult<()>").unwrap();
+
+  assert!(eq(&~"fn", &signature.prefix));
+  assert!(eq(&~"<T: Show>", &signature.generics));
+  assert!(signature.params.len() == 3);
+  assert!(eq(&~"|T| -> uint", &signature.params[1].ty));
+  assert!(eq(&~"std::hashmap::HashMap<~str, T>", &signature.params[2].ty));
+  assert!(signature.output == Some(~"IoResult<()>"));
+}
+
+#[test]
+fn test_signature_parse_closure_parameters() {
+  let signature = Signature::parse("fn A(writer: &mut Writer, f: |int, int| -> int, g: ||) -> int").unwrap();
+
+  assert!(signature.params.len() == 3);
+  assert!(eq(&~"|int, int| -> int", &signature.params[1].ty));
+  assert!(eq(&~"||", &signature.params[2].ty));
+  assert!(signature.output == Some(~"int"));
+}
+
+#[test]
+fn test_signature_returns_io_result() {
+  assert!(Signature::parse("fn A(writer: &mut Writer)").unwrap().returns_io_result());
+  assert!(Signature::parse("fn A(writer: &mut Writer) -> ::std::io::IoResult<()>").unwrap().returns_io_result());
+  assert!(!Signature::parse("fn A(writer: &mut Writer, f: |int| -> int)  -> int").unwrap().returns_io_result());
+  assert!(!Signature::parse("fn A(writer: &mut Writer) -> IoResult<~str>").unwrap().returns_io_result());
+}
+
+#[test]
+fn test_signature_arguments_with_patterns() {
+  let signature = Signature::parse("fn Point(writer: &mut Writer, mut x: int, (a, b): (int, int))").unwrap();
+
+  assert!(signature.params[1].name() == Some(~"x"));
+  assert!(signature.arguments(1).is_none());
+}
+
+#[test]
+fn test_signature_with_writer() {
+  let signature = Signature::parse("pub fn Page<'a>(name: &'a str)").unwrap().with_writer();
+
+  assert!(eq(&~"pub fn Page<'a, ErsWriter: Writer>(writer: &mut ErsWriter, name: &'a str)", &format!("{}", signature)));
+
+  let signature = Signature::parse("fn Empty() -> IoResult<()>").unwrap().with_writer();
+  assert!(eq(&~"fn Empty<ErsWriter: Writer>(writer: &mut ErsWriter) -> IoResult<()>", &format!("{}", signature)));
+}
+
+#[test]
+fn test_structure_parse() {
+  let structure = Structure::parse(" pub struct UserPage<'a, T: Show> { pub user: &'a User, flash: Option<T>, } ").unwrap();
+
+  assert!(eq(&~"pub struct", &structure.prefix));
+  assert!(eq(&~"UserPage", &structure.name));
+  assert!(structure.fields == ~[Param{pattern: ~"user",  ty: ~"&'a User"},
+                                Param{pattern: ~"flash", ty: ~"Option<T>"}]);
+  assert!(eq(&~"UserPage<'a, T>", &structure.type_name()));
+  assert!(eq(&~"UserPage { user: ref user, flash: ref flash }", &structure.destructuring()));
+}
+
+#[test]
+fn test_structure_parse_invalid() {
+  assert!(Structure::parse("pub fn Page(writer: &mut Writer)").is_none());
+  assert!(Structure::parse("struct Empty { }").is_none());
+  assert!(Structure::parse("struct Unit;").is_none());
+}
+
+#[test]
+fn test_signature_parse_invalid() {
+  assert!(Signature::parse("struct Page { name: ~str }").is_none());
+  assert!(Signature::parse("pub fn Page(writer: &mut Writer").is_none());
+  assert!(Signature::parse("pub fn Page(writer) where").is_none());
+}
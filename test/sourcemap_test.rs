@@ -0,0 +1,144 @@
+extern crate ers;
+extern crate serialize;
+
+use std::str::eq;
+use std::io::MemWriter;
+use serialize::json;
+use ers::{Template, WriteOptions};
+use ers::blocks::{Header, Declaration, Code, Text, Print, Block, Pos, Location};
+use ers::sourcemap::{SourceMap, LineCounter, Mapping};
+
+fn pos(line_no: int, column: int) -> Pos {
+  Pos::new(Location::new(line_no, column, 0), Location::new(line_no, column, 0))
+}
+
+fn sample_map() -> SourceMap {
+  let mut map = SourceMap::new(~"build/page.rs");
+
+  map.add(4,  6,  &Block{class: Code, content: ~"", pos: pos(1, 1), path: ~"page.ers"});
+  map.add(10, 11, &Block{class: Text, content: ~"", pos: pos(3, 5), path: ~"partials/header.ers"});
+  map
+}
+
+#[test]
+fn test_line_counter() {
+  let mut writer = MemWriter::new();
+  {
+    let mut counter = LineCounter::new(&mut writer);
+
+    assert!(counter.line() == 1);
+    counter.write_str("a\nb\n\nc").unwrap();
+    assert!(counter.line() == 4);
+  }
+  assert!(writer.get_ref() == bytes!("a\nb\n\nc"));
+}
+
+#[test]
+fn test_template_write_formatted_mapped() {
+  let mut map = SourceMap::new(~"foo.rs");
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Header,      content: ~"use std::io;", pos: pos(1, 1), path: ~"foo.ers"},
+                           ~Block{class: Declaration, content: ~"pub fn A(writer: &mut Writer)", pos: pos(2, 1), path: ~"foo.ers"},
+                           ~Block{class: Text,        content: ~"a", pos: pos(3, 4), path: ~"foo.ers"}
+                           ]);
+
+  tmpl.write_formatted_mapped(&mut MemWriter::new(), &WriteOptions::new(), &mut map).unwrap();
+
+  assert!(map.mappings == ~[Mapping{generated: 4,  end: 4,  path: ~"foo.ers", line_no: 1, column: 1},
+                            Mapping{generated: 7,  end: 7,  path: ~"foo.ers", line_no: 2, column: 1},
+                            Mapping{generated: 10, end: 10, path: ~"foo.ers", line_no: 3, column: 4}]);
+}
+
+#[test]
+fn test_sourcemap_lookup_generated_by_ers() {
+  let mut map     = SourceMap::new(~"foo.rs");
+  let mut writer  = MemWriter::new();
+  let mut options = WriteOptions::new();
+  options.to_string = true;
+
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~"pub fn A(writer: &mut Writer)", pos: pos(1, 1), path: ~"foo.ers"},
+                           ~Block{class: Text,        content: ~"a\nb\n", pos: pos(1, 32), path: ~"foo.ers"}
+                           ]);
+  tmpl.write_formatted_mapped(&mut writer, &options, &mut map).unwrap();
+
+  let output = std::str::from_utf8(writer.get_ref()).unwrap();
+  let line_of = |code: &str| output.lines().position(|line| line.starts_with(code)).unwrap() + 1;
+
+  assert!(map.lookup(line_of("try!(writer.write_str(")) == Some((~"foo.ers", 1, 32)));
+  assert!(map.lookup(line_of("try!(writer.flush());")) == None);
+  assert!(map.lookup(line_of("pub static A_SIZE_HINT")) == None);
+  assert!(map.lookup(line_of("pub fn A_to_string")) == None);
+  assert!(map.lookup(line_of("try!(A(&mut writer));")) == None);
+}
+
+#[test]
+fn test_sourcemap_lookup_generated_by_ers_for_struct() {
+  let mut map     = SourceMap::new(~"foo.rs");
+  let mut writer  = MemWriter::new();
+  let mut options = WriteOptions::new();
+  options.to_string = true;
+
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~"pub struct Page {\n  name: ~str\n}", pos: pos(1, 4), path: ~"foo.ers"},
+                           ~Block{class: Print,       content: ~" name ", pos: pos(4, 1), path: ~"foo.ers"}
+                           ]);
+  tmpl.write_formatted_mapped(&mut writer, &options, &mut map).unwrap();
+
+  let output = std::str::from_utf8(writer.get_ref()).unwrap();
+  let line_of = |code: &str| output.lines().position(|line| line.starts_with(code)).unwrap() + 1;
+
+  assert!(map.lookup(line_of("pub struct Page {")) == Some((~"foo.ers", 1, 4)));
+  assert!(map.lookup(line_of("  name: ~str")) == Some((~"foo.ers", 2, 1)));
+  assert!(map.lookup(line_of("impl Page {")) == None);
+  assert!(map.lookup(line_of("#[allow(unused_variable)]")) == None);
+  assert!(map.lookup(line_of("pub fn render(")) == None);
+  assert!(map.lookup(line_of("let Page {")) == None);
+  assert!(map.lookup(line_of("try!(writer.write_str(ers::runtime::escape_html(")) == Some((~"foo.ers", 4, 1)));
+  assert!(map.lookup(line_of("pub fn to_string(")) == None);
+}
+
+#[test]
+fn test_sourcemap_lookup() {
+  let map = sample_map();
+
+  assert!(map.lookup(3)  == None);
+  assert!(map.lookup(4)  == Some((~"page.ers", 1, 1)));
+  assert!(map.lookup(6)  == Some((~"page.ers", 3, 1)));
+  assert!(map.lookup(7)  == None);
+  assert!(map.lookup(10) == Some((~"partials/header.ers", 3, 5)));
+  assert!(map.lookup(12) == None);
+}
+
+#[test]
+fn test_sourcemap_write_and_parse() {
+  let map = sample_map();
+  let mut writer = MemWriter::new();
+
+  map.write(&mut writer).unwrap();
+  let output = std::str::from_utf8(writer.get_ref()).unwrap();
+
+  assert!(eq(&~"ers-sourcemap build/page.rs\n4 6 1 1 page.ers\n10 11 3 5 partials/header.ers\n", &output.to_owned()), output.to_owned());
+  assert!(SourceMap::parse(output) == Ok(map));
+  assert!(SourceMap::parse("4 1 1 page.ers").is_err());
+  assert!(SourceMap::parse("ers-sourcemap build/page.rs\n4 1 1 page.ers").is_err());
+}
+
+#[test]
+fn test_sourcemap_explain_text() {
+  let map = sample_map();
+
+  assert!(eq(&~"partials/header.ers:3:5: error: unresolved name `nme`.",
+             &map.explain("build/page.rs:10:26: 10:29 error: unresolved name `nme`.")));
+  assert!(eq(&~"error: aborting due to previous error", &map.explain("error: aborting due to previous error")));
+  assert!(eq(&~"build/page.rs:8:1: 8:4 error: mismatched types", &map.explain("build/page.rs:8:1: 8:4 error: mismatched types")));
+}
+
+#[test]
+fn test_sourcemap_explain_json() {
+  let map = sample_map();
+  let output = map.explain("{\"message\": \"mismatched types\", \"spans\": [{\"file_name\": \"build/page.rs\", \"line_start\": 10, \"column_start\": 2, \"line_end\": 11, \"column_end\": 8}], \"children\": []}");
+
+  let expected = json::from_str("{\"message\": \"mismatched types\", \"spans\": [{\"file_name\": \"partials/header.ers\", \"line_start\": 3, \"column_start\": 5, \"line_end\": 4, \"column_end\": 1}], \"children\": []}").unwrap();
+  assert!(json::from_str(output).unwrap() == expected, output);
+}
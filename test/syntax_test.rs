@@ -0,0 +1,63 @@
+extern crate ers;
+
+use std::str::eq;
+use ers::Syntax;
+
+#[test]
+fn test_syntax_new() {
+  let syntax = Syntax::new();
+
+  assert!(eq(&~"<%", &syntax.open));
+  assert!(eq(&~"%>", &syntax.close));
+  assert!(eq(&~"%%>", &syntax.header_close()));
+}
+
+#[test]
+fn test_syntax_from_directive() {
+  let syntax = Syntax::from_directive(" open=[[ close=]] header=$ trim=~ partial=& ").unwrap();
+
+  assert!(eq(&~"[[", &syntax.open));
+  assert!(eq(&~"]]", &syntax.close));
+  assert!(syntax.header == '$');
+  assert!(syntax.trim == '~');
+  assert!(syntax.partial == '&');
+  assert!(syntax.print == '=');
+  assert!(eq(&~"$]]", &syntax.header_close()));
+}
+
+#[test]
+fn test_syntax_from_empty_directive() {
+  assert!(Syntax::from_directive("") == Ok(Syntax::new()));
+}
+
+#[test]
+fn test_syntax_from_directive_unknown_setting() {
+  assert!(Syntax::from_directive("opening=[[").is_err());
+}
+
+#[test]
+fn test_syntax_from_directive_long_sigil() {
+  assert!(Syntax::from_directive("print=:=").is_err());
+}
+
+#[test]
+fn test_syntax_from_directive_missing_value() {
+  assert!(Syntax::from_directive("open").is_err());
+  assert!(Syntax::from_directive("close=").is_err());
+}
+
+#[test]
+fn test_syntax_from_directive_ambiguous_sigils() {
+  assert!(Syntax::from_directive("debug==") == Err(~"`print` and `debug` are both `=`"));
+  assert!(Syntax::from_directive("trim=%") == Err(~"`trim` cannot start `close`"));
+  assert!(Syntax::from_directive("open=%% close=%%") == Err(~"`open` and `close` must differ"));
+}
+
+#[test]
+fn test_syntax_validate() {
+  let mut syntax = Syntax::new();
+  assert!(syntax.validate().is_ok());
+
+  syntax.open = ~"";
+  assert!(syntax.validate() == Err(~"`open` and `close` cannot be empty"));
+}
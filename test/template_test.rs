@@ -1,9 +1,22 @@
 extern crate ers;
 
-use std::io::BufferedWriter;
+use std::str;
+use std::io;
+use std::io::{BufferedWriter, MemWriter, IoResult};
 use std::io::fs::File;
-use ers::blocks::{Text,Header,Declaration,Code,Print,Block,Pos};
-use ers::Template;
+use ers::blocks::{Text,Header,Declaration,Code,Print,Raw,Debug,Comment,Partial,If,Elsif,Else,For,End,Block,Pos,Location};
+use ers::{Template, WriteOptions};
+use ers::template::{DeclarationNotFound, DeclarationAfterContent, UnsupportedDeclaration, UnsupportedReturnType, SizeHintClash, UnsupportedPartial, WriteFailure};
+
+fn pos(line_no: int) -> Pos {
+  Pos::new(Location::new(line_no, 1, 0), Location::new(line_no, 1, 0))
+}
+
+fn write_block(block: Block) -> ~str {
+  let mut writer = MemWriter::new();
+  block.write(&mut writer).unwrap();
+  str::from_utf8(writer.get_ref()).unwrap().to_owned()
+}
 
 #[test]
 fn test_template_new() {
@@ -21,17 +34,533 @@ fn test_template_write() {
 
   let tmpl:Template =
     Template::new(~"foo.ers",~[
-                  ~Block{class: Text,        content: ~"<html>",       pos: Pos{line_no: 4}},
-                  ~Block{class: Header,      content: ~"use std::io;", pos: Pos{line_no: 1}},
-                  ~Block{class: Declaration, content: ~"pub fn Template(writer: &mut Writer, i : int)", pos: Pos{line_no: 2}},
-                  ~Block{class: Code,        content: ~"for n in range(0, i - 1) {", pos: Pos{line_no: 3}},
-                  ~Block{class: Text,        content: ~"<p class=\"foobar\">",          pos: Pos{line_no: 5}},
-                  ~Block{class: Print,       content: ~"n + 1",        pos: Pos{line_no: 6}},
-                  ~Block{class: Text,        content: ~"</p>",         pos: Pos{line_no: 7}},
-                  ~Block{class: Code,        content: ~"}",            pos: Pos{line_no: 8}},
-                  ~Block{class: Text,        content: ~"</html>",      pos: Pos{line_no: 9}}
+                  ~Block{class: Header,      content: ~"use std::io;", pos: pos(1), path: ~"foo.ers"},
+                  ~Block{class: Declaration, content: ~"pub fn Template(writer: &mut Writer, i : int)", pos: pos(2), path: ~"foo.ers"},
+                  ~Block{class: Text,        content: ~"<html>",       pos: pos(4), path: ~"foo.ers"},
+                  ~Block{class: Code,        content: ~"for n in range(0, i - 1) {", pos: pos(3), path: ~"foo.ers"},
+                  ~Block{class: Text,        content: ~"<p class=\"foobar\">",          pos: pos(5), path: ~"foo.ers"},
+                  ~Block{class: Print,       content: ~"n + 1",        pos: pos(6), path: ~"foo.ers"},
+                  ~Block{class: Text,        content: ~"</p>",         pos: pos(7), path: ~"foo.ers"},
+                  ~Block{class: Code,        content: ~"}",            pos: pos(8), path: ~"foo.ers"},
+                  ~Block{class: Text,        content: ~"</html>",      pos: pos(9), path: ~"foo.ers"}
                   ]);
-  tmpl.write_formatted(&mut out_writer);
+  assert!(tmpl.write_formatted(&mut out_writer).is_ok());
 
   out_writer.flush();
 }
+
+#[test]
+fn test_template_write_to_string() {
+  let path = "build/bar.rs";
+  let mut out_writer = BufferedWriter::new(~File::create(&Path::new(path)).unwrap() as ~Writer);
+  let mut options = WriteOptions::new();
+  options.to_string = true;
+
+  let tmpl:Template =
+    Template::new(~"bar.ers",~[
+                  ~Block{class: Declaration, content: ~"pub fn Greeting(writer: &mut Writer, name: &str)", pos: pos(1), path: ~"bar.ers"},
+                  ~Block{class: Text,        content: ~"Hello, ",      pos: pos(2), path: ~"bar.ers"},
+                  ~Block{class: Print,       content: ~"name",         pos: pos(2), path: ~"bar.ers"},
+                  ~Block{class: Text,        content: ~"!",            pos: pos(2), path: ~"bar.ers"},
+                  ~Block{class: Declaration, content: ~"pub fn Bytes(writer: &mut Writer, bytes: &[u8])", pos: pos(3), path: ~"bar.ers"},
+                  ~Block{class: Code,        content: ~"try!(writer.write(bytes));", pos: pos(4), path: ~"bar.ers"}
+                  ]);
+  assert!(tmpl.write_formatted_with(&mut out_writer, &options).is_ok());
+
+  out_writer.flush();
+}
+
+#[test]
+fn test_template_write_formatted() {
+  let mut writer = MemWriter::new();
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Header,      content: ~"use std::io;", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Declaration, content: ~"pub fn Template(writer: &mut Writer, i : int)", pos: pos(2), path: ~"foo.ers"},
+                           ~Block{class: Comment,     content: ~" prints i ",   pos: pos(3), path: ~"foo.ers"},
+                           ~Block{class: Print,       content: ~" i ",          pos: pos(3), path: ~"foo.ers"}
+                           ]);
+  tmpl.write_formatted(&mut writer);
+
+  let output = str::from_utf8(writer.get_ref()).unwrap();
+  assert!(str::eq(&~"extern crate ers;\n\
+                     \n//line 1\n\
+                     use std::io;\n\
+                     \n//line 2\n\
+                     pub fn Template(writer: &mut Writer, i : int) -> ::std::io::IoResult<()> {\n\
+                     \n//line 3\n\
+                     try!(writer.write_str(ers::runtime::escape_html(format!(\"{}\",  i ))));\n\
+                     try!(writer.flush());\n\
+                     Ok(())\n\
+                     }\n\n\
+                     pub static TEMPLATE_SIZE_HINT: uint = 0;\n\
+                     \n", &output.to_owned()), output.to_owned());
+}
+
+#[test]
+fn test_template_write_formatted_inner_attribute() {
+  let mut writer = MemWriter::new();
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Header,      content: ~"use std::io;", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Header,      content: ~"#![feature(phase)]", pos: pos(2), path: ~"foo.ers"},
+                           ~Block{class: Declaration, content: ~"pub fn A(writer: &mut Writer)", pos: pos(3), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.write_formatted(&mut writer) == Ok(3));
+
+  let output = str::from_utf8(writer.get_ref()).unwrap();
+  assert!(output.starts_with("\n//line 2\n#![feature(phase)]\nextern crate ers;\n\n//line 1\nuse std::io;\n"), output.to_owned());
+}
+
+#[test]
+fn test_template_write_formatted_extern_crate_header() {
+  let mut writer = MemWriter::new();
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Header,      content: ~"extern crate ers;", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Declaration, content: ~"pub fn A(writer: &mut Writer)", pos: pos(2), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.write_formatted(&mut writer) == Ok(2));
+
+  let output = str::from_utf8(writer.get_ref()).unwrap();
+  assert!(output.starts_with("\n//line 1\nextern crate ers;\n\n//line 2\n"), output.to_owned());
+  assert!(output.match_indices("extern crate ers;").count() == 1, output.to_owned());
+}
+
+#[test]
+fn test_template_write_formatted_to_string() {
+  let mut writer  = MemWriter::new();
+  let mut options = WriteOptions::new();
+  options.to_string = true;
+
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~" pub fn A(writer: &mut Writer, name: &str) ", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Text,        content: ~"Hi ",    pos: pos(2), path: ~"foo.ers"},
+                           ~Block{class: Print,       content: ~" name ", pos: pos(2), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.write_formatted_with(&mut writer, &options) == Ok(3));
+
+  let output = str::from_utf8(writer.get_ref()).unwrap();
+  assert!(output.ends_with("}\n\n\
+                            pub static A_SIZE_HINT: uint = 3;\n\
+                            static mut A_LEARNED_SIZE: ::std::sync::atomics::AtomicUint = ::std::sync::atomics::INIT_ATOMIC_UINT;\n\
+                            \n\
+                            pub fn A_to_string(name: &str) -> ::std::io::IoResult<~str> {\n\
+                            let mut writer = ::std::io::MemWriter::with_capacity(unsafe { ers::runtime::size_hint(A_SIZE_HINT, &A_LEARNED_SIZE) });\n\
+                            try!(A(&mut writer, name));\n\
+                            unsafe { ers::runtime::learn_size(&mut A_LEARNED_SIZE, writer.get_ref().len()); }\n\
+                            match ::std::str::from_utf8_owned(writer.unwrap()) {\n\
+                            Some(output) => Ok(output),\n\
+                            None => Err(::std::io::IoError{kind: ::std::io::InvalidInput, desc: \"template rendered invalid UTF-8\", detail: None})\n\
+                            }\n\
+                            }\n\n"), output.to_owned());
+}
+
+#[test]
+fn test_template_write_formatted_to_string_return_type() {
+  let mut writer  = MemWriter::new();
+  let mut options = WriteOptions::new();
+  options.to_string = true;
+
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~" pub fn A(writer: &mut Writer) -> IoResult<()> ", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Text,        content: ~"Hi",    pos: pos(2), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.write_formatted_with(&mut writer, &options) == Ok(2));
+
+  let output = str::from_utf8(writer.get_ref()).unwrap();
+  assert!(output.contains("\n//line 1\n pub fn A(writer: &mut Writer) -> IoResult<()>  {\n"), output.to_owned());
+  assert!(output.contains("pub fn A_to_string() -> ::std::io::IoResult<~str> {\n"), output.to_owned());
+  assert!(output.contains("try!(A(&mut writer));\n"), output.to_owned());
+}
+
+#[test]
+fn test_template_write_formatted_generic_writer() {
+  let mut writer  = MemWriter::new();
+  let mut options = WriteOptions::new();
+  options.generic_writer = true;
+  options.to_string      = true;
+
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~" pub fn A(name: &str) ", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Print,       content: ~" name ", pos: pos(2), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.write_formatted_with(&mut writer, &options) == Ok(2));
+
+  let output = str::from_utf8(writer.get_ref()).unwrap();
+  assert!(str::eq(&~"extern crate ers;\n\
+                     \n//line 1\n\
+                     pub fn A<ErsWriter: Writer>(writer: &mut ErsWriter, name: &str) -> ::std::io::IoResult<()> {\n\
+                     \n//line 2\n\
+                     try!(writer.write_str(ers::runtime::escape_html(format!(\"{}\",  name ))));\n\
+                     try!(writer.flush());\n\
+                     Ok(())\n\
+                     }\n\n\
+                     pub static A_SIZE_HINT: uint = 0;\n\
+                     static mut A_LEARNED_SIZE: ::std::sync::atomics::AtomicUint = ::std::sync::atomics::INIT_ATOMIC_UINT;\n\
+                     \n\
+                     pub fn A_to_string(name: &str) -> ::std::io::IoResult<~str> {\n\
+                     let mut writer = ::std::io::MemWriter::with_capacity(unsafe { ers::runtime::size_hint(A_SIZE_HINT, &A_LEARNED_SIZE) });\n\
+                     try!(A(&mut writer, name));\n\
+                     unsafe { ers::runtime::learn_size(&mut A_LEARNED_SIZE, writer.get_ref().len()); }\n\
+                     match ::std::str::from_utf8_owned(writer.unwrap()) {\n\
+                     Some(output) => Ok(output),\n\
+                     None => Err(::std::io::IoError{kind: ::std::io::InvalidInput, desc: \"template rendered invalid UTF-8\", detail: None})\n\
+                     }\n\
+                     }\n\n", &output.to_owned()), output.to_owned());
+}
+
+#[test]
+fn test_template_write_formatted_struct() {
+  let mut writer  = MemWriter::new();
+  let mut options = WriteOptions::new();
+  options.to_string = true;
+
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~" pub struct Page { name: ~str } ", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Print,       content: ~" name ", pos: pos(2), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.write_formatted_with(&mut writer, &options) == Ok(2));
+
+  let output = str::from_utf8(writer.get_ref()).unwrap();
+  assert!(str::eq(&~"extern crate ers;\n\
+                     \n//line 1\n\
+                     pub struct Page { name: ~str }\n\
+                     impl Page {\n\
+                     #[allow(unused_variable)]\n\
+                     pub fn render(&self, writer: &mut Writer) -> ::std::io::IoResult<()> {\n\
+                     let Page { name: ref name } = *self;\n\
+                     \n//line 2\n\
+                     try!(writer.write_str(ers::runtime::escape_html(format!(\"{}\",  name ))));\n\
+                     try!(writer.flush());\n\
+                     Ok(())\n\
+                     }\n\n\
+                     pub fn to_string(&self) -> ::std::io::IoResult<~str> {\n\
+                     let mut writer = ::std::io::MemWriter::with_capacity(unsafe { ers::runtime::size_hint(PAGE_SIZE_HINT, &PAGE_LEARNED_SIZE) });\n\
+                     try!(self.render(&mut writer));\n\
+                     unsafe { ers::runtime::learn_size(&mut PAGE_LEARNED_SIZE, writer.get_ref().len()); }\n\
+                     match ::std::str::from_utf8_owned(writer.unwrap()) {\n\
+                     Some(output) => Ok(output),\n\
+                     None => Err(::std::io::IoError{kind: ::std::io::InvalidInput, desc: \"template rendered invalid UTF-8\", detail: None})\n\
+                     }\n\
+                     }\n\
+                     }\n\n\
+                     pub static PAGE_SIZE_HINT: uint = 0;\n\
+                     static mut PAGE_LEARNED_SIZE: ::std::sync::atomics::AtomicUint = ::std::sync::atomics::INIT_ATOMIC_UINT;\n\
+                     \n", &output.to_owned()), output.to_owned());
+}
+
+#[test]
+fn test_template_write_formatted_byte_strings() {
+  let mut writer  = MemWriter::new();
+  let mut options = WriteOptions::new();
+  options.byte_strings = true;
+
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~"pub fn A(writer: &mut Writer)", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Text,        content: ~"<p class=\"é\">\n", pos: pos(2), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.write_formatted_with(&mut writer, &options) == Ok(2));
+
+  let output = str::from_utf8(writer.get_ref()).unwrap();
+  assert!(output.contains("\n//line 2\ntry!(writer.write(bytes!(\"<p class=\\\"\\u00e9\\\">\\n\")));\n"), output.to_owned());
+}
+
+#[test]
+fn test_template_write_formatted_to_string_unsupported_declaration() {
+  let mut writer  = MemWriter::new();
+  let mut options = WriteOptions::new();
+  options.to_string = true;
+
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~"pub fn A(writer: &mut Writer, (x, y): (int, int))", pos: pos(1), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.write_formatted_with(&mut writer, &options) == Err(UnsupportedDeclaration(~"foo.ers", pos(1))));
+  assert!(writer.get_ref().is_empty());
+}
+
+struct BrokenWriter;
+
+impl Writer for BrokenWriter {
+  fn write(&mut self, _buf: &[u8]) -> IoResult<()> {
+    Err(io::standard_error(io::BrokenPipe))
+  }
+}
+
+#[test]
+fn test_template_write_formatted_write_failure() {
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~"pub fn A(writer: &mut Writer)", pos: pos(1), path: ~"foo.ers"}
+                           ]);
+
+  match tmpl.write_formatted(&mut BrokenWriter) {
+    Err(WriteFailure(error)) => assert!(error.kind == io::BrokenPipe),
+    _                        => fail!("write failure not reported")
+  }
+}
+
+#[test]
+fn test_template_coalesce() {
+  let span = |start: uint, end: uint| Pos::new(Location::new(1, 1 + start as int, start), Location::new(1, 1 + end as int, end));
+  let mut tmpl = Template::new(~"foo.ers", ~[
+                               ~Block{class: Text,  content: ~"<html>", pos: span(0, 6),   path: ~"foo.ers"},
+                               ~Block{class: Text,  content: ~"\n",     pos: span(6, 7),   path: ~"foo.ers"},
+                               ~Block{class: Text,  content: ~"<p>",    pos: span(7, 10),  path: ~"foo.ers"},
+                               ~Block{class: Print, content: ~" x ",    pos: span(10, 18), path: ~"foo.ers"},
+                               ~Block{class: Text,    content: ~"</p>",   pos: span(18, 22), path: ~"foo.ers"},
+                               ~Block{class: Comment, content: ~" note ", pos: span(22, 32), path: ~"foo.ers"},
+                               ~Block{class: Text,    content: ~"\n",     pos: span(32, 33), path: ~"foo.ers"},
+                               ~Block{class: Text,    content: ~"<h1>",   pos: span(0, 4),   path: ~"header.ers"}
+                               ]);
+
+  tmpl.coalesce();
+
+  assert!(tmpl.blocks == ~[
+          ~Block{class: Text,    content: ~"<html>\n<p>", pos: span(0, 10),  path: ~"foo.ers"},
+          ~Block{class: Print,   content: ~" x ",         pos: span(10, 18), path: ~"foo.ers"},
+          ~Block{class: Text,    content: ~"</p>\n",      pos: span(18, 33), path: ~"foo.ers"},
+          ~Block{class: Comment, content: ~" note ",      pos: span(22, 32), path: ~"foo.ers"},
+          ~Block{class: Text,    content: ~"<h1>",        pos: span(0, 4),   path: ~"header.ers"}
+          ]);
+}
+
+#[test]
+fn test_template_write_formatted_coalesces_text() {
+  let mut writer = MemWriter::new();
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~"pub fn A(writer: &mut Writer)", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Text,        content: ~"<p>",     pos: pos(2), path: ~"foo.ers"},
+                           ~Block{class: Comment,     content: ~" note ",  pos: pos(2), path: ~"foo.ers"},
+                           ~Block{class: Text,        content: ~"</p>",    pos: pos(2), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.write_formatted(&mut writer) == Ok(2));
+
+  let output = str::from_utf8(writer.get_ref()).unwrap();
+  assert!(output.contains("\n//line 2\ntry!(writer.write_str(\"<p></p>\"));\ntry!(writer.flush());\n"), output.to_owned());
+}
+
+#[test]
+fn test_template_validate() {
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Comment,     content: ~" a note ", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Text,        content: ~"\n  ",     pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Declaration, content: ~"pub fn Template(writer: &mut Writer)", pos: pos(2), path: ~"foo.ers"},
+                           ~Block{class: Header,      content: ~"use std::io;", pos: pos(3), path: ~"foo.ers"},
+                           ~Block{class: Text,        content: ~"<html>",   pos: pos(4), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.validate() == Ok(()));
+}
+
+#[test]
+fn test_template_validate_return_type() {
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~"pub fn A(writer: &mut Writer) -> std::io::IoResult< () >", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Declaration, content: ~"pub fn B(writer: &mut Writer, f: |int| -> int) -> int", pos: pos(2), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.validate() == Err(UnsupportedReturnType(~"foo.ers", pos(2))));
+}
+
+#[test]
+fn test_template_validate_closure_return_type() {
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~"pub fn A(writer: &mut Writer, f: |int, int| -> int) -> int", pos: pos(1), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.validate() == Err(UnsupportedReturnType(~"foo.ers", pos(1))));
+}
+
+#[test]
+fn test_template_validate_unsupported_declaration() {
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~"pub fn A(writer)", pos: pos(1), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.validate() == Err(UnsupportedDeclaration(~"foo.ers", pos(1))));
+}
+
+#[test]
+fn test_template_validate_size_hint_clash() {
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~"pub fn Page(writer: &mut Writer)", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Declaration, content: ~"pub struct page { name: ~str }",   pos: pos(2), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.validate() == Err(SizeHintClash(~"foo.ers", pos(2))));
+}
+
+#[test]
+fn test_template_validate_partial_without_call() {
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Declaration, content: ~"pub fn Template(writer: &mut Writer)", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Partial,     content: ~"user_card",  pos: pos(2), path: ~"card.ers"}
+                           ]);
+
+  assert!(tmpl.validate() == Err(UnsupportedPartial(~"card.ers", pos(2))));
+  assert!(format!("{}", tmpl.validate().unwrap_err()) == ~"card.ers:2:1: expected a call like `name(args)`");
+}
+
+#[test]
+fn test_block_write_partial_without_call() {
+  let block = Block{class: Partial, content: ~"user_card", pos: pos(2), path: ~"foo.ers"};
+
+  assert!(block.write(&mut MemWriter::new()).is_err());
+}
+
+#[test]
+fn test_template_write_formatted_declaration_not_found() {
+  let mut writer = MemWriter::new();
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Header, content: ~"use std::io;", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Text,   content: ~"<html>",       pos: pos(2), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.write_formatted(&mut writer) == Err(DeclarationNotFound));
+  assert!(writer.get_ref().is_empty());
+}
+
+#[test]
+fn test_template_write_formatted_multiple_declarations() {
+  let mut writer = MemWriter::new();
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Text,        content: ~"\n",       pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Declaration, content: ~"pub fn A(writer: &mut Writer)", pos: pos(2), path: ~"foo.ers"},
+                           ~Block{class: Text,        content: ~"a",        pos: pos(3), path: ~"foo.ers"},
+                           ~Block{class: Header,      content: ~"use std::io;", pos: pos(4), path: ~"foo.ers"},
+                           ~Block{class: Declaration, content: ~"pub fn B(writer: &mut Writer)", pos: pos(5), path: ~"foo.ers"},
+                           ~Block{class: Text,        content: ~"b",        pos: pos(6), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.write_formatted(&mut writer) == Ok(5));
+
+  let output = str::from_utf8(writer.get_ref()).unwrap();
+  assert!(str::eq(&~"extern crate ers;\n\
+                     \n//line 4\n\
+                     use std::io;\n\
+                     \n//line 2\n\
+                     pub fn A(writer: &mut Writer) -> ::std::io::IoResult<()> {\n\
+                     \n//line 3\n\
+                     try!(writer.write_str(\"a\"));\n\
+                     try!(writer.flush());\n\
+                     Ok(())\n\
+                     }\n\n\
+                     pub static A_SIZE_HINT: uint = 1;\n\
+                     \n\
+                     \n//line 5\n\
+                     pub fn B(writer: &mut Writer) -> ::std::io::IoResult<()> {\n\
+                     \n//line 6\n\
+                     try!(writer.write_str(\"b\"));\n\
+                     try!(writer.flush());\n\
+                     Ok(())\n\
+                     }\n\n\
+                     pub static B_SIZE_HINT: uint = 1;\n\
+                     \n", &output.to_owned()), output.to_owned());
+}
+
+#[test]
+fn test_template_write_formatted_declaration_after_content() {
+  let mut writer = MemWriter::new();
+  let tmpl = Template::new(~"foo.ers", ~[
+                           ~Block{class: Text,        content: ~"<html>", pos: pos(1), path: ~"foo.ers"},
+                           ~Block{class: Declaration, content: ~"pub fn A(writer: &mut Writer)", pos: pos(2), path: ~"foo.ers"}
+                           ]);
+
+  assert!(tmpl.write_formatted(&mut writer) == Err(DeclarationAfterContent(~"foo.ers", pos(2))));
+}
+
+#[test]
+fn test_block_write_comment() {
+  let output = write_block(Block{class: Comment, content: ~" note ", pos: pos(6), path: ~"foo.ers"});
+
+  assert!(output.is_empty(), output);
+}
+
+#[test]
+fn test_block_write_print_escapes_html() {
+  let output = write_block(Block{class: Print, content: ~" n + 1 ", pos: pos(6), path: ~"foo.ers"});
+
+  assert!(str::eq(&~"\n//line 6\ntry!(writer.write_str(ers::runtime::escape_html(format!(\"{}\",  n + 1 ))));\n", &output), output);
+}
+
+#[test]
+fn test_block_write_print_string_expression() {
+  let output = write_block(Block{class: Print, content: ~" \"a\\tb\" ", pos: pos(6), path: ~"foo.ers"});
+
+  assert!(str::eq(&~"\n//line 6\ntry!(writer.write_str(ers::runtime::escape_html(format!(\"{}\",  \"a\\tb\" ))));\n", &output), output);
+}
+
+#[test]
+fn test_block_write_raw() {
+  let output = write_block(Block{class: Raw, content: ~" n + 1 ", pos: pos(6), path: ~"foo.ers"});
+
+  assert!(str::eq(&~"\n//line 6\ntry!(writer.write_str(format!(\"{}\",  n + 1 )));\n", &output), output);
+}
+
+#[test]
+fn test_block_write_debug() {
+  let output = write_block(Block{class: Debug, content: ~" n + 1 ", pos: pos(6), path: ~"foo.ers"});
+
+  assert!(str::eq(&~"\n//line 6\ntry!(writer.write_str(ers::runtime::escape_html(format!(\"{:?}\",  n + 1 ))));\n", &output), output);
+}
+
+#[test]
+fn test_block_write_partial() {
+  let output = write_block(Block{class: Partial, content: ~"user_card(&user)", pos: pos(6), path: ~"foo.ers"});
+
+  assert!(str::eq(&~"\n//line 6\ntry!(user_card(writer, &user));\n", &output), output);
+}
+
+#[test]
+fn test_block_write_partial_without_arguments() {
+  let output = write_block(Block{class: Partial, content: ~"footer()", pos: pos(6), path: ~"foo.ers"});
+
+  assert!(str::eq(&~"\n//line 6\ntry!(footer(writer));\n", &output), output);
+}
+
+#[test]
+fn test_block_write_declaration_return_type() {
+  let output = write_block(Block{class: Declaration, content: ~" pub fn A(writer: &mut Writer) -> IoResult<()> ", pos: pos(1), path: ~"foo.ers"});
+
+  assert!(str::eq(&~"\n//line 1\n pub fn A(writer: &mut Writer) -> IoResult<()>  {\n", &output), output);
+}
+
+#[test]
+fn test_block_write_declaration_closure_parameter() {
+  let output = write_block(Block{class: Declaration, content: ~" pub fn A(writer: &mut Writer, f: |int| -> int) ", pos: pos(1), path: ~"foo.ers"});
+
+  assert!(str::eq(&~"\n//line 1\n pub fn A(writer: &mut Writer, f: |int| -> int) -> ::std::io::IoResult<()> {\n", &output), output);
+
+  let output = write_block(Block{class: Declaration, content: ~" pub fn A(writer: &mut Writer, f: |int, int| -> int) ", pos: pos(1), path: ~"foo.ers"});
+
+  assert!(str::eq(&~"\n//line 1\n pub fn A(writer: &mut Writer, f: |int, int| -> int) -> ::std::io::IoResult<()> {\n", &output), output);
+
+  let output = write_block(Block{class: Declaration, content: ~" pub fn A(writer: &mut Writer, f: |int, int| -> int) -> IoResult<()> ", pos: pos(1), path: ~"foo.ers"});
+
+  assert!(str::eq(&~"\n//line 1\n pub fn A(writer: &mut Writer, f: |int, int| -> int) -> IoResult<()> {\n", &output), output);
+}
+
+#[test]
+fn test_block_write_unsupported_declaration() {
+  let mut writer = MemWriter::new();
+  let block = Block{class: Declaration, content: ~"pub fn A(writer)", pos: pos(1), path: ~"foo.ers"};
+
+  assert!(block.write(&mut writer).is_err());
+}
+
+#[test]
+fn test_block_write_control_flow() {
+  let output = write_block(Block{class: If, content: ~"n > 0", pos: pos(2), path: ~"foo.ers"}) +
+               write_block(Block{class: Elsif, content: ~"n < 0", pos: pos(3), path: ~"foo.ers"}) +
+               write_block(Block{class: Else, content: ~"", pos: pos(4), path: ~"foo.ers"}) +
+               write_block(Block{class: For, content: ~"x in xs.iter()", pos: pos(5), path: ~"foo.ers"}) +
+               write_block(Block{class: End, content: ~"", pos: pos(6), path: ~"foo.ers"});
+
+  assert!(str::eq(&~"\n//line 2\nif n > 0 {\n\n//line 3\n} else if n < 0 {\n\n//line 4\n} else {\n\n//line 5\nfor x in xs.iter() {\n\n//line 6\n}\n", &output), output);
+}
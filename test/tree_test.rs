@@ -0,0 +1,53 @@
+extern crate ers;
+
+use ers::Parser;
+use ers::blocks::{Text, Print, If, Else, For};
+use ers::tree::{Node, Leaf, Conditional, Loop};
+
+fn tree(source: &str) -> ~[Node] {
+  let template = match Parser::new().parse_str(~"inline.ers", source) {
+    Ok(template) => template,
+    Err(error)   => fail!("{}", error)
+  };
+  template.tree().unwrap()
+}
+
+#[test]
+fn test_tree_build_nested() {
+  let nodes = tree("<ul><% for x in xs %><% if x > 1 %><%= x %><% else %>-<% end %><% end %></ul>");
+
+  assert!(nodes.len() == 3);
+  match nodes[0] { Leaf(ref block) => assert!(block.class == Text), _ => fail!("expected a leaf") }
+  match nodes[2] { Leaf(ref block) => assert!(block.class == Text), _ => fail!("expected a leaf") }
+
+  match nodes[1] {
+    Loop(ref opener, ref body, _) => {
+      assert!(opener.class == For && opener.content == ~"x in xs");
+      assert!(body.len() == 1);
+
+      match body[0] {
+        Conditional(ref branches, _) => {
+          assert!(branches.len() == 2);
+
+          let (ref first, ref first_body) = branches[0];
+          assert!(first.class == If && first.content == ~"x > 1");
+          assert!(first_body.len() == 1);
+          match first_body[0] { Leaf(ref block) => assert!(block.class == Print), _ => fail!("expected a leaf") }
+
+          let (ref second, ref second_body) = branches[1];
+          assert!(second.class == Else && second_body.len() == 1);
+        },
+        _ => fail!("expected a conditional")
+      }
+    },
+    _ => fail!("expected a loop")
+  }
+}
+
+#[test]
+fn test_tree_build_flat() {
+  let nodes = tree("<% for n in range(0, 3) { %><%= n %><% } %>");
+
+  assert!(nodes.len() == 3);
+  assert!(nodes.iter().all(|node| match *node { Leaf(_) => true, _ => false }));
+}
@@ -0,0 +1,91 @@
+extern crate ers;
+extern crate collections;
+
+use std::str::eq;
+use collections::TreeMap;
+use ers::value::{Context, ToValue, Null, Boolean, Number, String, List, Object};
+
+#[test]
+fn test_value_show() {
+  let mut fields = TreeMap::new();
+  fields.insert(~"name", String(~"Ann"));
+
+  assert!(eq(&format!("{}", Number(3.0)), &~"3"));
+  assert!(eq(&format!("{}", Number(1.5)), &~"1.5"));
+  assert!(eq(&format!("{}", Null), &~""));
+  assert!(eq(&format!("{}", List(~[Number(1.0), String(~"a")])), &~"[1, a]"));
+  assert!(eq(&format!("{}", Object(fields)), &~"{name: Ann}"));
+}
+
+#[test]
+fn test_value_debug() {
+  assert!(eq(&List(~[Number(1.0), String(~"a\"b")]).debug(), &~"[1, \"a\\\"b\"]"));
+}
+
+#[test]
+fn test_value_is_truthy() {
+  assert!(!Null.is_truthy());
+  assert!(!Boolean(false).is_truthy());
+  assert!(Number(0.0).is_truthy());
+  assert!(String(~"").is_truthy());
+}
+
+#[test]
+fn test_value_context() {
+  let mut context = Context::new();
+
+  assert!(context.set(~"n", Number(1.0)).is_none());
+  assert!(context.set(~"n", Number(2.0)) == Some(Number(1.0)));
+  assert!(context.get("n") == Some(&Number(2.0)));
+  assert!(context.get("m").is_none());
+}
+
+#[test]
+fn test_value_context_scopes() {
+  let mut context = Context::new();
+  context.set(~"n", Number(1.0));
+  context.set(~"m", Number(1.0));
+
+  context.push();
+  context.set(~"n", Number(2.0));
+  assert!(context.get("n") == Some(&Number(2.0)));
+  assert!(context.get("m") == Some(&Number(1.0)));
+
+  context.pop();
+  assert!(context.get("n") == Some(&Number(1.0)));
+
+  context.pop();
+  assert!(context.get("m") == Some(&Number(1.0)));
+}
+
+#[test]
+fn test_value_to_value() {
+  let mut fields = TreeMap::new();
+  fields.insert(~"id", 7u);
+
+  assert!(true.to_value() == Boolean(true));
+  assert!(3i.to_value() == Number(3.0));
+  assert!("a".to_value() == String(~"a"));
+  assert!(Some(~"b").to_value() == String(~"b"));
+  assert!((None::<int>).to_value() == Null);
+  assert!((~[1i, 2]).to_value() == List(~[Number(1.0), Number(2.0)]));
+  assert!(fields.to_value().debug() == ~"{id: 7}");
+}
+
+#[test]
+fn test_value_context_from_json() {
+  match Context::from_json("{\"name\": \"Ann\", \"tags\": [1, null], \"admin\": true}") {
+    Ok(context) => {
+      assert!(context.get("name") == Some(&String(~"Ann")));
+      assert!(context.get("tags") == Some(&List(~[Number(1.0), Null])));
+      assert!(context.get("admin") == Some(&Boolean(true)));
+    },
+    Err(message) => fail!("{}", message)
+  }
+
+  match Context::from_json("[1]") {
+    Err(message) => assert!(eq(&message, &~"expected a JSON object, found a list"), message),
+    Ok(_)        => fail!("loaded a JSON list as a context")
+  }
+  assert!(Context::from_json("{").is_err());
+}